@@ -0,0 +1,177 @@
+//! Synthetic models shared by the benchmarks, so they run without any asset files.
+#![allow(dead_code)]
+
+use dot_vox::{Model, Size, Voxel};
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Small xorshift generator, so generated models are identical between runs.
+pub struct Rng(u32);
+
+impl Rng {
+    pub fn new(seed: u32) -> Rng {
+        Rng(seed.max(1))
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 17;
+        self.0 ^= self.0 << 5;
+        self.0
+    }
+
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 / (1 << 24) as f32
+    }
+}
+
+fn voxel(x: u32, y: u32, z: u32, i: u32) -> Voxel {
+    Voxel { x: x as u8, y: y as u8, z: z as u8, i: i as u8 }
+}
+
+fn model(size: u32, voxels: Vec<Voxel>) -> Model {
+    Model { size: Size { x: size, y: size, z: size }, voxels }
+}
+
+/// Every voxel of a `size`³ volume filled.
+pub fn solid_cube(size: u32) -> Model {
+    let mut voxels = Vec::new();
+    for z in 0..size {
+        for y in 0..size {
+            for x in 0..size {
+                voxels.push(voxel(x, y, z, (x + y + z) % 254 + 1));
+            }
+        }
+    }
+    model(size, voxels)
+}
+
+/// Randomly scattered voxels, filling roughly `density` of a `size`³ volume.
+pub fn sparse_noise(size: u32, density: f32) -> Model {
+    let mut rng = Rng::new(0x5eed);
+    let mut voxels = Vec::new();
+    for z in 0..size {
+        for y in 0..size {
+            for x in 0..size {
+                if rng.next_f32() < density {
+                    voxels.push(voxel(x, y, z, rng.next_u32() % 254 + 1));
+                }
+            }
+        }
+    }
+    model(size, voxels)
+}
+
+/// A one voxel thick sphere shell touching the sides of a `size`³ volume, coloured in bands by
+/// height.
+pub fn hollow_shell(size: u32) -> Model {
+    let radius = size as f32 / 2.0 - 0.5;
+    let mut voxels = Vec::new();
+    for z in 0..size {
+        for y in 0..size {
+            for x in 0..size {
+                let (dx, dy, dz) = (x as f32 - radius, y as f32 - radius, z as f32 - radius);
+                let distance = (dx * dx + dy * dy + dz * dz).sqrt();
+                if distance <= radius && distance > radius - 1.0 {
+                    voxels.push(voxel(x, y, z, z / 16 + 1));
+                }
+            }
+        }
+    }
+    model(size, voxels)
+}
+
+/// Rolling hills filling each column of a `size`² footprint up to a smoothed random height of at
+/// most `max_height`.
+pub fn terrain(size: u32, max_height: u32) -> Model {
+    const CELL: u32 = 16;
+    let mut rng = Rng::new(0x7e44a1);
+    let cells = size / CELL + 2;
+    let corners: Vec<f32> = (0..cells * cells).map(|_| rng.next_f32()).collect();
+    let corner = |x: u32, y: u32| corners[(y * cells + x) as usize];
+    let mut voxels = Vec::new();
+    for y in 0..size {
+        for x in 0..size {
+            let (cx, cy) = (x / CELL, y / CELL);
+            let (fx, fy) = ((x % CELL) as f32 / CELL as f32, (y % CELL) as f32 / CELL as f32);
+            let top = corner(cx, cy) + (corner(cx + 1, cy) - corner(cx, cy)) * fx;
+            let bottom = corner(cx, cy + 1) + (corner(cx + 1, cy + 1) - corner(cx, cy + 1)) * fx;
+            let height = ((top + (bottom - top) * fy) * max_height as f32) as u32 + 1;
+            for z in 0..height.min(max_height) {
+                voxels.push(voxel(x, y, z, if z + 1 == height { 1 } else { 2 }));
+            }
+        }
+    }
+    Model { size: Size { x: size, y: size, z: max_height }, voxels }
+}
+
+/// Encodes a model and palette as a version 150 `.vox` file.
+pub fn encode(model: &Model, palette: &[u32]) -> Vec<u8> {
+    fn u32s(out: &mut Vec<u8>, values: &[u32]) {
+        for value in values {
+            out.extend((0..4).map(|shift| (value >> (shift * 8)) as u8));
+        }
+    }
+    fn chunk(out: &mut Vec<u8>, id: &[u8], content: &[u8]) {
+        out.extend_from_slice(id);
+        u32s(out, &[content.len() as u32, 0]);
+        out.extend_from_slice(content);
+    }
+
+    let mut size = Vec::new();
+    u32s(&mut size, &[model.size.x, model.size.y, model.size.z]);
+    let mut xyzi = Vec::new();
+    u32s(&mut xyzi, &[model.voxels.len() as u32]);
+    for v in &model.voxels {
+        xyzi.extend_from_slice(&[v.x, v.y, v.z, v.i.saturating_add(1)]);
+    }
+    let mut rgba = Vec::new();
+    u32s(&mut rgba, &palette[..256]);
+
+    let mut children = Vec::new();
+    chunk(&mut children, b"SIZE", &size);
+    chunk(&mut children, b"XYZI", &xyzi);
+    chunk(&mut children, b"RGBA", &rgba);
+
+    let mut file = b"VOX ".to_vec();
+    u32s(&mut file, &[150]);
+    file.extend_from_slice(b"MAIN");
+    u32s(&mut file, &[0, children.len() as u32]);
+    file.extend(children);
+    file
+}
+
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+/// Global allocator wrapping `System` that records the peak number of bytes in use.
+pub struct CountingAllocator;
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let current = CURRENT.fetch_add(layout.size(), Ordering::SeqCst) + layout.size();
+        let mut peak = PEAK.load(Ordering::SeqCst);
+        while current > peak {
+            match PEAK.compare_exchange_weak(peak, current, Ordering::SeqCst, Ordering::SeqCst) {
+                Ok(_) => break,
+                Err(actual) => peak = actual,
+            }
+        }
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        CURRENT.fetch_sub(layout.size(), Ordering::SeqCst);
+        System.dealloc(ptr, layout)
+    }
+}
+
+/// Runs `f`, returning the most memory it had allocated at any one time. Only meaningful when
+/// `CountingAllocator` is the global allocator.
+pub fn peak_allocation<F: FnOnce()>(f: F) -> usize {
+    let start = CURRENT.load(Ordering::SeqCst);
+    PEAK.store(start, Ordering::SeqCst);
+    f();
+    PEAK.load(Ordering::SeqCst) - start
+}
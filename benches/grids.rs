@@ -0,0 +1,73 @@
+#[macro_use]
+extern crate criterion;
+extern crate dot_vox;
+extern crate dot_vox_amethyst;
+
+mod common;
+
+use criterion::{Benchmark, Criterion};
+use dot_vox_amethyst::{DenseGrid, OctreeGrid, VoxelGrid};
+
+/// Side of the world the grids are compared on, 128 million cells in all.
+const WORLD: u32 = 512;
+
+/// Cells of a mostly empty world: rolling terrain a fraction of the world high.
+fn cells() -> Vec<([u32; 3], u8)> {
+    common::terrain(256, 32).voxels.iter()
+        .flat_map(|voxel| {
+            let (x, y, z) = (voxel.x as u32 * 2, voxel.y as u32 * 2, voxel.z as u32);
+            (0..4).map(move |corner| ([x + corner % 2, y + corner / 2, z], voxel.i))
+        })
+        .collect()
+}
+
+fn fill<G: VoxelGrid>(mut grid: G, cells: &[([u32; 3], u8)]) -> G {
+    for &(position, index) in cells {
+        grid.set(position, Some(index));
+    }
+    grid
+}
+
+fn insert(c: &mut Criterion) {
+    let (dense_cells, octree_cells) = (cells(), cells());
+    c.bench(
+        "grid_insert",
+        Benchmark::new("dense", move |b| {
+            b.iter_with_large_drop(|| fill(DenseGrid::new([WORLD; 3]), &dense_cells))
+        }).with_function("octree", move |b| {
+            b.iter_with_large_drop(|| fill(OctreeGrid::new([WORLD; 3]), &octree_cells))
+        }).sample_size(10),
+    );
+}
+
+fn query(c: &mut Criterion) {
+    let cells = cells();
+    let dense = fill(DenseGrid::new([WORLD; 3]), &cells);
+    let octree = fill(OctreeGrid::new([WORLD; 3]), &cells);
+    let mut rng = common::Rng::new(0x5eed);
+    let positions: Vec<[u32; 3]> = (0..100_000)
+        .map(|_| [rng.next_u32() % WORLD, rng.next_u32() % WORLD, rng.next_u32() % 64])
+        .collect();
+    let octree_positions = positions.clone();
+    c.bench(
+        "grid_query",
+        Benchmark::new("dense", move |b| {
+            b.iter(|| positions.iter().filter(|&&at| dense.get(at).is_some()).count())
+        }).with_function("octree", move |b| {
+            b.iter(|| octree_positions.iter().filter(|&&at| octree.get(at).is_some()).count())
+        }),
+    );
+}
+
+/// Prints the bytes used by each grid, as `import` does for whole imports.
+fn memory(_: &mut Criterion) {
+    let cells = cells();
+    let dense = fill(DenseGrid::new([WORLD; 3]), &cells);
+    let octree = fill(OctreeGrid::new([WORLD; 3]), &cells);
+    println!("{:<10} {:>10} {:>16}", "grid", "voxels", "bytes");
+    println!("{:<10} {:>10} {:>16}", "dense", dense.len(), dense.memory_usage());
+    println!("{:<10} {:>10} {:>16}", "octree", octree.len(), octree.memory_usage());
+}
+
+criterion_group!(benches, insert, query, memory);
+criterion_main!(benches);
@@ -0,0 +1,104 @@
+#[macro_use]
+extern crate criterion;
+extern crate amethyst_assets;
+extern crate dot_vox;
+extern crate dot_vox_amethyst;
+
+mod common;
+
+use amethyst_assets::SimpleFormat;
+use common::CountingAllocator;
+use criterion::{Criterion, ParameterizedBenchmark, Throughput};
+use dot_vox::Model;
+use dot_vox_amethyst::{mesh_model, DotVoxFormat};
+
+use std::fmt;
+
+#[global_allocator]
+static ALLOCATOR: CountingAllocator = CountingAllocator;
+
+struct Case {
+    name: &'static str,
+    model: Model,
+    palette: Vec<u32>,
+    bytes: Vec<u8>,
+    vertices: usize,
+}
+
+impl fmt::Debug for Case {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+fn cases() -> Vec<Case> {
+    let palette = dot_vox::DEFAULT_PALETTE.to_vec();
+    vec!(
+        ("solid_cube_32", common::solid_cube(32)),
+        ("sparse_noise_128", common::sparse_noise(128, 0.02)),
+        ("hollow_shell_64", common::hollow_shell(64)),
+        ("terrain_64", common::terrain(64, 32)),
+    ).into_iter()
+        .map(|(name, model)| Case {
+            name,
+            bytes: common::encode(&model, &palette),
+            vertices: mesh_model(&model, &palette).len(),
+            model,
+            palette: palette.clone(),
+        })
+        .collect()
+}
+
+fn parse(c: &mut Criterion) {
+    c.bench(
+        "parse",
+        ParameterizedBenchmark::new(
+            "load_bytes",
+            |b, case: &Case| b.iter(|| dot_vox::load_bytes(&case.bytes).unwrap()),
+            cases(),
+        ).throughput(|case| Throughput::Bytes(case.bytes.len() as u32)),
+    );
+}
+
+fn mesh(c: &mut Criterion) {
+    c.bench(
+        "mesh",
+        ParameterizedBenchmark::new(
+            "mesh_model",
+            |b, case: &Case| b.iter_with_large_drop(|| mesh_model(&case.model, &case.palette)),
+            cases(),
+        ).throughput(|case| Throughput::Elements(case.vertices as u32))
+            .sample_size(20),
+    );
+}
+
+fn import(c: &mut Criterion) {
+    c.bench(
+        "import",
+        ParameterizedBenchmark::new(
+            "dot_vox_format",
+            |b, case: &Case| b.iter_with_large_drop(|| {
+                DotVoxFormat.import(case.bytes.clone(), Default::default()).unwrap()
+            }),
+            cases(),
+        ).throughput(|case| Throughput::Elements(case.vertices as u32))
+            .sample_size(20),
+    );
+}
+
+/// Criterion has no notion of memory use, so the peak allocation of a full import is printed
+/// alongside the timings.
+fn memory(_: &mut Criterion) {
+    println!("{:<20} {:>10} {:>12} {:>16}", "model", "voxels", "vertices", "peak bytes");
+    for case in cases() {
+        let bytes = case.bytes.clone();
+        let peak = common::peak_allocation(|| {
+            DotVoxFormat.import(bytes, Default::default()).unwrap();
+        });
+        println!("{:<20} {:>10} {:>12} {:>16}",
+                 case.name, case.model.voxels.len(), case.vertices, peak);
+    }
+}
+
+criterion_group!(benches, parse, mesh, import, memory);
+criterion_main!(benches);
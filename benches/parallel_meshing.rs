@@ -0,0 +1,28 @@
+#[macro_use]
+extern crate criterion;
+extern crate dot_vox;
+extern crate dot_vox_amethyst;
+
+mod common;
+
+use criterion::{Benchmark, Criterion};
+use dot_vox_amethyst::{mesh_model, mesh_model_parallel};
+
+use std::sync::Arc;
+
+fn meshing(c: &mut Criterion) {
+    let model = Arc::new(common::hollow_shell(256));
+    let palette = Arc::new(dot_vox::DEFAULT_PALETTE.to_vec());
+    let (parallel_model, parallel_palette) = (model.clone(), palette.clone());
+    c.bench(
+        "mesh_256_sphere_shell",
+        Benchmark::new("sequential", move |b| b.iter(|| mesh_model(&model, &palette)))
+            .with_function("parallel", move |b| {
+                b.iter(|| mesh_model_parallel(&parallel_model, &parallel_palette))
+            })
+            .sample_size(10),
+    );
+}
+
+criterion_group!(benches, meshing);
+criterion_main!(benches);
@@ -4,14 +4,13 @@ extern crate dot_vox_amethyst;
 extern crate gfx_core;
 
 use amethyst::LoggerConfig;
-use amethyst::assets::{AssetStorage, Loader};
+use amethyst::assets::{AssetStorage, HotReloadBundle, Loader};
 use amethyst::core::{GlobalTransform, Transform, TransformBundle};
 use amethyst::core::cgmath::{Deg, Matrix4, Vector3};
 use amethyst::ecs::{Component, DenseVecStorage, Join, ReadStorage, System, WriteStorage};
 use amethyst::prelude::*;
-use amethyst::renderer::{Camera, DisplayConfig, Event, KeyboardInput, MaterialDefaults, Mesh,
-                         Pipeline, PosColor, Projection, RenderBundle, Stage, WindowEvent,
-                         VirtualKeyCode};
+use amethyst::renderer::{Camera, DisplayConfig, Event, KeyboardInput, Mesh, Pipeline, PosColor,
+                         Projection, RenderBundle, Stage, WindowEvent, VirtualKeyCode};
 use amethyst::utils::fps_counter::FPSCounterBundle;
 use dot_vox_amethyst::{DotVoxFormat, DrawVoxels};
 
@@ -30,6 +29,7 @@ fn run() -> Result<(), amethyst::Error> {
 
     let game_data = GameDataBuilder::default()
         .with_bundle(TransformBundle::new())?
+        .with_bundle(HotReloadBundle::default())?
         .with_bundle(RenderBundle::new(pipe, Some(config)))?
         .with_bundle(FPSCounterBundle::default())?
         .with(RotationSystem, "rotation_system", &[]);
@@ -56,19 +56,16 @@ impl<'a, 'b> State<GameData<'a, 'b>> for Example {
     fn on_start(&mut self, data: StateData<GameData>) {
         let world = data.world;
 
-        let (mesh, material) = {
+        let mesh = {
             let loader = world.read_resource::<Loader>();
             let mesh_storage = world.read_resource::<AssetStorage<Mesh>>();
-            let mat_defaults = world.read_resource::<MaterialDefaults>();
-            let mesh = loader.load(
+            loader.load(
                 "resources/mesh/placeholder.vox",
                 DotVoxFormat,
                 Default::default(),
                 (),
                 &mesh_storage,
-            );
-            let material = mat_defaults.0.clone();
-            (mesh, material)
+            )
         };
 
         let mut transform = Transform::default();
@@ -77,7 +74,6 @@ impl<'a, 'b> State<GameData<'a, 'b>> for Example {
         world.create_entity()
             .with(Name("Model"))
             .with(mesh)
-            .with(material)
             .with(transform)
             .with(GlobalTransform(Matrix4::from_translation(Vector3::new(0.0, 0.0, 0.0))))
             .build();
@@ -0,0 +1,362 @@
+use amethyst_assets::{AssetStorage, Result as AssetResult, SimpleFormat};
+use amethyst_core::GlobalTransform;
+use amethyst_renderer::{ActiveCamera, Camera, Color, Effect, Encoder, Factory, Mesh, MeshData,
+                         MeshHandle, NewEffect, PosColor, Position, Query};
+use amethyst_renderer::error::Result;
+use amethyst_renderer::pipe::DepthMode;
+use amethyst_renderer::pipe::pass::{Pass, PassData};
+use dot_vox::Model;
+use dot_vox_format::{self, DotVoxOptions};
+use fog::FogArgs;
+use gfx_core::Primitive;
+use glsl_layout::*;
+use renderer::{draw_mesh, get_camera, FragmentArgs, VertexArgs, Warnings};
+use shading::triangle_normal;
+
+use specs::{Component, DenseVecStorage, Join, Read, ReadStorage};
+
+use std::collections::HashSet;
+use std::marker::PhantomData;
+
+static VERT_SRC: &[u8] = include_bytes!("shaders/vertex/basic.glsl");
+static FRAG_SRC: &[u8] = include_bytes!("shaders/fragment/flat.glsl");
+
+const WIREFRAME_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
+const CELL_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+const NORMAL_LENGTH: f32 = 0.5;
+
+/// Which debug layers to draw.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DebugModes {
+    /// Edges of every triangle of the mesh.
+    pub wireframe: bool,
+    /// Outline of every voxel cell of the model.
+    pub cells: bool,
+    /// A line along the normal of every triangle, coloured by its direction.
+    pub normals: bool,
+}
+
+impl DebugModes {
+    fn union(self, other: DebugModes) -> DebugModes {
+        DebugModes {
+            wireframe: self.wireframe || other.wireframe,
+            cells: self.cells || other.cells,
+            normals: self.normals || other.normals,
+        }
+    }
+}
+
+/// Resource turning debug layers on for every entity with a `VoxelDebug`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct VoxelDebugSettings {
+    pub modes: DebugModes,
+}
+
+/// Debug meshes of an entity, imported with `DotVoxDebugFormat`. A layer is drawn when it is
+/// enabled either here or in `VoxelDebugSettings` and its mesh is set.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VoxelDebug {
+    pub modes: DebugModes,
+    pub wireframe: Option<MeshHandle>,
+    pub cells: Option<MeshHandle>,
+    pub normals: Option<MeshHandle>,
+}
+
+impl Component for VoxelDebug {
+    type Storage = DenseVecStorage<Self>;
+}
+
+/// Kind of debug mesh produced by `DotVoxDebugFormat`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DebugMeshKind {
+    Wireframe,
+    Cells,
+    Normals,
+}
+
+/// Options accepted by `DotVoxDebugFormat`.
+#[derive(Clone, Debug)]
+pub struct DebugMeshOptions {
+    pub kind: DebugMeshKind,
+    /// Options of the mesh being debugged, so the lines match it.
+    pub mesh: DotVoxOptions,
+}
+
+/// Imports a `.vox` file as a line list for `DrawVoxelDebug`.
+#[derive(Clone)]
+pub struct DotVoxDebugFormat;
+
+impl SimpleFormat<Mesh> for DotVoxDebugFormat {
+    const NAME: &'static str = "MAGICAVOXEL_DOT_VOX_DEBUG";
+    type Options = DebugMeshOptions;
+
+    fn import(&self, bytes: Vec<u8>, options: DebugMeshOptions) -> AssetResult<MeshData> {
+        let lines = match options.kind {
+            DebugMeshKind::Wireframe => {
+                wireframe_lines(&dot_vox_format::mesh(&bytes, &options.mesh)?)
+            }
+            DebugMeshKind::Normals => normal_lines(&dot_vox_format::mesh(&bytes, &options.mesh)?),
+            DebugMeshKind::Cells => {
+                let (models, _) = dot_vox_format::selected_models(&bytes, &options.mesh)?;
+                let mut lines = Vec::new();
+                for (_, model, origin) in models {
+                    let model = dot_vox_format::prepare(model, &options.mesh);
+                    let mut model_lines = cell_lines(&model);
+                    dot_vox_format::to_source_space(&mut model_lines, &options.mesh);
+                    for vertex in &mut model_lines {
+                        for axis in 0..3 {
+                            vertex.position[axis] += origin[axis];
+                        }
+                    }
+                    lines.extend(model_lines);
+                }
+                options.mesh.limits.check_vertices(lines.len())?;
+                lines
+            }
+        };
+        Ok(MeshData::PosColor(lines))
+    }
+}
+
+/// Every distinct edge of a triangle list, as pairs of line vertices.
+pub fn wireframe_lines(triangles: &[PosColor]) -> Vec<PosColor> {
+    let mut seen = HashSet::new();
+    let mut lines = Vec::new();
+    for triangle in triangles.chunks(3).filter(|t| t.len() == 3) {
+        for &(a, b) in &[(0, 1), (1, 2), (2, 0)] {
+            let (a, b) = (triangle[a].position, triangle[b].position);
+            let key = if position_key(a) < position_key(b) {
+                (position_key(a), position_key(b))
+            } else {
+                (position_key(b), position_key(a))
+            };
+            if seen.insert(key) {
+                lines.push(PosColor { position: a, color: WIREFRAME_COLOR });
+                lines.push(PosColor { position: b, color: WIREFRAME_COLOR });
+            }
+        }
+    }
+    lines
+}
+
+/// The 12 edges of every voxel cell of a model.
+pub fn cell_lines(model: &Model) -> Vec<PosColor> {
+    let mut lines = Vec::with_capacity(model.voxels.len() * 24);
+    for voxel in &model.voxels {
+        let centre = [voxel.x as f32, voxel.y as f32, voxel.z as f32];
+        let corner = |bits: usize| {
+            let mut position = centre;
+            for axis in 0..3 {
+                position[axis] += if bits >> axis & 1 == 1 { 0.5 } else { -0.5 };
+            }
+            PosColor { position, color: CELL_COLOR }
+        };
+        for start in 0..8 {
+            for axis in 0..3 {
+                if start >> axis & 1 == 0 {
+                    lines.push(corner(start));
+                    lines.push(corner(start | 1 << axis));
+                }
+            }
+        }
+    }
+    lines
+}
+
+/// A line from the centre of every triangle along its normal. The colour is the absolute normal,
+/// so faces along x are red, along y green and along z blue.
+pub fn normal_lines(triangles: &[PosColor]) -> Vec<PosColor> {
+    let mut lines = Vec::with_capacity(triangles.len() / 3 * 2);
+    for triangle in triangles.chunks(3).filter(|t| t.len() == 3) {
+        let normal = match triangle_normal(triangle) {
+            Some(normal) => normal,
+            None => continue,
+        };
+        let (a, b, c) = (triangle[0].position, triangle[1].position, triangle[2].position);
+        let mut start = [0.0; 3];
+        let mut end = [0.0; 3];
+        let mut color = [1.0; 4];
+        for axis in 0..3 {
+            let direction = normal[axis];
+            start[axis] = (a[axis] + b[axis] + c[axis]) / 3.0;
+            end[axis] = start[axis] + direction * NORMAL_LENGTH;
+            color[axis] = direction.abs();
+        }
+        lines.push(PosColor { position: start, color });
+        lines.push(PosColor { position: end, color });
+    }
+    lines
+}
+
+fn position_key(position: [f32; 3]) -> [u32; 3] {
+    [position[0].to_bits(), position[1].to_bits(), position[2].to_bits()]
+}
+
+/// Draws the debug layers of entities with a `VoxelDebug` and a `GlobalTransform` as lines.
+/// Add it after the pass drawing the meshes themselves, so the lines are drawn on top.
+/// `V` is `VertexFormat`
+#[derive(Derivative, Clone, Debug, PartialEq)]
+#[derivative(Default(bound = "V: Query<(Position, Color)>, Self: Pass"))]
+pub struct DrawVoxelDebug<V> {
+    _pd: PhantomData<V>,
+    warnings: Warnings,
+}
+
+impl<V> DrawVoxelDebug<V>
+    where
+        V: Query<(Position, Color)>,
+        Self: Pass,
+{
+    /// Create instance of `DrawVoxelDebug` pass
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl<'a, V> PassData<'a> for DrawVoxelDebug<V>
+    where
+        V: Query<(Position, Color)>,
+{
+    type Data = (
+        Option<Read<'a, ActiveCamera>>,
+        ReadStorage<'a, Camera>,
+        Read<'a, AssetStorage<Mesh>>,
+        Read<'a, VoxelDebugSettings>,
+        ReadStorage<'a, VoxelDebug>,
+        ReadStorage<'a, GlobalTransform>,
+    );
+}
+
+impl<V> Pass for DrawVoxelDebug<V>
+    where
+        V: Query<(Position, Color)>,
+{
+    fn compile(&mut self, effect: NewEffect) -> Result<Effect> {
+        use std::mem;
+        let mut builder = effect.simple(VERT_SRC, FRAG_SRC);
+        builder
+            .with_raw_constant_buffer(
+                "VertexArgs",
+                mem::size_of::<<VertexArgs as Uniform>::Std140>(),
+                1,
+            )
+            .with_raw_constant_buffer(
+                "FragmentArgs",
+                mem::size_of::<<FragmentArgs as Uniform>::Std140>(),
+                1,
+            )
+            .with_raw_vertex_buffer(V::QUERIED_ATTRIBUTES, V::size() as u8, 0)
+            .with_primitive_type(Primitive::LineList);
+        builder.with_output("color", Some(DepthMode::LessEqualTest));
+        builder.build()
+    }
+
+    fn apply<'a, 'b: 'a>(
+        &'a mut self,
+        encoder: &mut Encoder,
+        effect: &mut Effect,
+        _factory: Factory,
+        (
+            active,
+            camera,
+            mesh_storage,
+            settings,
+            debug,
+            global,
+        ): <Self as PassData<'a>>::Data,
+    ) {
+        let camera = get_camera(active, &camera, &global);
+
+        for (debug, global) in (&debug, &global).join() {
+            let modes = debug.modes.union(settings.modes);
+            let layers = [
+                (modes.wireframe, &debug.wireframe),
+                (modes.cells, &debug.cells),
+                (modes.normals, &debug.normals),
+            ];
+            for &(enabled, handle) in &layers {
+                if let (true, &Some(ref handle)) = (enabled, handle) {
+                    draw_mesh(
+                        encoder,
+                        effect,
+                        &mut self.warnings,
+                        mesh_storage.get(handle),
+                        camera,
+                        Some(global),
+                        None,
+                        &FogArgs::default(),
+                        &[V::QUERIED_ATTRIBUTES],
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use dot_vox::{Size, Voxel};
+    use scene::tests::SceneBuilder;
+    use scene::LayerFilter;
+    use super::*;
+
+    fn pos(x: f32, y: f32, z: f32) -> PosColor {
+        PosColor { position: [x, y, z], color: [0.5; 4] }
+    }
+
+    #[test]
+    fn shared_triangle_edges_are_drawn_once() {
+        let quad = vec!(pos(0.0, 0.0, 0.0), pos(1.0, 0.0, 0.0), pos(1.0, 1.0, 0.0),
+                        pos(1.0, 1.0, 0.0), pos(0.0, 1.0, 0.0), pos(0.0, 0.0, 0.0));
+        let lines = wireframe_lines(&quad);
+        assert_eq!(lines.len(), 10);
+        assert!(lines.iter().all(|vertex| vertex.color == WIREFRAME_COLOR));
+    }
+
+    #[test]
+    fn every_cell_gets_twelve_unit_edges() {
+        let model = Model {
+            size: Size { x: 2, y: 1, z: 1 },
+            voxels: vec!(Voxel { x: 0, y: 0, z: 0, i: 1 }, Voxel { x: 1, y: 0, z: 0, i: 1 }),
+        };
+        let lines = cell_lines(&model);
+        assert_eq!(lines.len(), 48);
+        for edge in lines.chunks(2) {
+            let length: f32 = (0..3)
+                .map(|axis| (edge[1].position[axis] - edge[0].position[axis]).abs())
+                .sum();
+            assert_eq!(length, 1.0);
+        }
+        assert_eq!(lines[24].position, [0.5, -0.5, -0.5]);
+    }
+
+    #[test]
+    fn layered_cells_are_placed_like_the_scene() {
+        let scene = SceneBuilder::new()
+            .layer("", false)
+            .model(1, 0, [0; 3], false)
+            .model(2, 0, [4, 0, 0], false)
+            .build();
+        let options = DebugMeshOptions {
+            kind: DebugMeshKind::Cells,
+            mesh: DotVoxOptions { layers: Some(LayerFilter::All), ..Default::default() },
+        };
+        let lines = match DotVoxDebugFormat.import(scene, options).unwrap() {
+            MeshData::PosColor(vertices) => vertices,
+            result => panic!("Expected cell lines, received {:?}", result),
+        };
+        assert_eq!(lines.len(), 48);
+        assert!(lines[..24].iter().all(|vertex| vertex.position[0].abs() == 0.5));
+        assert!(lines[24..].iter().all(|vertex| (vertex.position[0] - 4.0).abs() == 0.5));
+    }
+
+    #[test]
+    fn normals_start_at_the_triangle_centre_and_are_coloured_by_direction() {
+        let triangle = vec!(pos(0.0, 0.0, 0.0), pos(3.0, 0.0, 0.0), pos(0.0, 3.0, 0.0));
+        let lines = normal_lines(&triangle);
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].position, [1.0, 1.0, 0.0]);
+        assert_eq!(lines[1].position, [1.0, 1.0, NORMAL_LENGTH]);
+        assert_eq!(lines[1].color, [0.0, 0.0, 1.0, 1.0]);
+    }
+}
@@ -1,98 +1,405 @@
 use amethyst_assets::{Result, SimpleFormat};
 use amethyst_renderer::{Mesh, MeshData, PosColor};
 use dot_vox;
-use dot_vox::Voxel;
+use dot_vox::{DotVoxData, Model, Voxel};
+use error::VoxError;
+use grid::{VoxelGrid, VoxelPosition};
+use limits::ImportLimits;
+use log::Level;
+use lod::{self, Lod, LodVoting};
+use meshing::{palette_to_rgba, CUBE_OFFSETS};
+use mesh_cache::{self, write_f32, write_u32, write_u64, MeshCache};
+use scene::{LayerFilter, Scene};
+use shading::{self, FaceShading};
+use stats::ImportStats;
+use surface_nets::{self, Extractor};
 
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// Imports a MagicaVoxel `.vox` file as a coloured triangle mesh: the model picked by
+/// `DotVoxOptions::model`, the first by default, or every model on the layers `layers` selects.
+///
+/// Meshes loaded through the `Loader` take part in hot reloading: with `HotReloadBundle` added,
+/// saving the `.vox` file re-imports it, and every entity holding the returned `MeshHandle` is
+/// drawn with the new mesh.
 #[derive(Clone)]
 pub struct DotVoxFormat;
 
+/// Options accepted by `DotVoxFormat`.
+#[derive(Clone, Debug, Default)]
+pub struct DotVoxOptions {
+    /// Index of the model to import when `layers` isn't set.
+    pub model: usize,
+    /// Directory holding precompiled meshes, keyed by a hash of the source file and these
+    /// options. When set, an up-to-date entry is used instead of meshing the model, and freshly
+    /// meshed models are written back to it.
+    pub cache_dir: Option<PathBuf>,
+    /// Downsamples the model before meshing, producing a cheaper mesh occupying the same space.
+    /// Import the file once per level and hand the handles to a `VoxelLod`.
+    pub lod: Option<Lod>,
+    /// Bakes a fixed brightness per face direction into the vertex colours.
+    pub shading: Option<FaceShading>,
+    /// Turns the voxels into cubes, or into a smooth surface.
+    pub extractor: Extractor,
+    /// Imports every model of the scene on the layers passing the filter, placed as in
+    /// MagicaVoxel, instead of just the first model.
+    pub layers: Option<LayerFilter>,
+    /// Palette indices, as in `Voxel::i`, reserved for gameplay markers. Their voxels are left
+    /// out of the mesh; `DotVoxMarkersFormat` reports where they are.
+    pub markers: Vec<u8>,
+    /// Caps on the voxels, vertices and models of the import.
+    pub limits: ImportLimits,
+}
+
+impl DotVoxOptions {
+    /// Hashes the source file with every option that changes the mesh, written out field by field
+    /// so that keys stay stable across releases. The cache directory and limits don't change the
+    /// mesh and are left out.
+    fn cache_key(&self, bytes: &[u8]) -> u64 {
+        let mut key = Vec::new();
+        write_u64(&mut key, mesh_cache::fnv_hash(bytes));
+        write_u64(&mut key, self.model as u64);
+        match self.lod {
+            None => key.push(0),
+            Some(lod) => {
+                let voting = match lod.voting {
+                    LodVoting::Majority => 0,
+                    LodVoting::AnySolid => 1,
+                };
+                key.extend_from_slice(&[1, lod.factor, voting]);
+            }
+        }
+        match self.shading {
+            None => key.push(0),
+            Some(shading) => {
+                key.push(1);
+                let FaceShading { top, x_sides, y_sides, bottom } = shading;
+                for &brightness in &[top, x_sides, y_sides, bottom] {
+                    write_f32(&mut key, brightness);
+                }
+            }
+        }
+        key.push(match self.extractor {
+            Extractor::Cubes => 0,
+            Extractor::SurfaceNets => 1,
+        });
+        match self.layers {
+            None => key.push(0),
+            Some(LayerFilter::All) => key.push(1),
+            Some(LayerFilter::Visible) => key.push(2),
+            Some(LayerFilter::Named(ref names)) => {
+                key.push(3);
+                write_u32(&mut key, names.len() as u32);
+                for name in names {
+                    write_u32(&mut key, name.len() as u32);
+                    key.extend_from_slice(name.as_bytes());
+                }
+            }
+        }
+        write_u32(&mut key, self.markers.len() as u32);
+        key.extend_from_slice(&self.markers);
+        mesh_cache::fnv_hash(&key)
+    }
+}
+
 impl SimpleFormat<Mesh> for DotVoxFormat {
     const NAME: &'static str = "MAGICAVOXEL_DOT_VOX";
-    type Options = ();
+    type Options = DotVoxOptions;
 
-    fn import(&self, bytes: Vec<u8>, _: ()) -> Result<MeshData> {
-        let data = dot_vox::load_bytes(&bytes)?;
-        let vertices = data.models[0].voxels.iter()
-            .flat_map(|voxel| voxel_to_cube(voxel, &data.palette))
-            .collect();
-        Ok(MeshData::PosColor(vertices))
+    fn import(&self, bytes: Vec<u8>, options: DotVoxOptions) -> Result<MeshData> {
+        let cache_dir = match options.cache_dir {
+            Some(ref dir) => dir,
+            None => return Ok(MeshData::PosColor(mesh(&bytes, &options)?)),
+        };
+        let key = options.cache_key(&bytes);
+        if let Some(cache) = mesh_cache::read_cache(cache_dir, key) {
+            select(&load(&bytes)?, &bytes, &options)?;
+            options.limits.check_vertices(cache.indices.len())?;
+            return Ok(MeshData::PosColor(cache.to_triangles()));
+        }
+        let meshed = mesh_timed(&bytes, &options)?;
+        let cache = MeshCache::from_triangles(key, &meshed.sub_meshes, &meshed.vertices);
+        mesh_cache::write_cache(cache_dir, &cache);
+        Ok(MeshData::PosColor(meshed.vertices))
     }
 }
 
-lazy_static! {
-    static ref CUBE_OFFSETS: Vec<(f32, f32, f32)> = vec!(
-        (-0.5, -0.5, -0.5),
-        (-0.5, -0.5, 0.5),
-        (-0.5, 0.5, 0.5),
-        (0.5, 0.5, -0.5),
-        (-0.5, -0.5, -0.5),
-        (-0.5, 0.5, -0.5),
-        (0.5, -0.5, 0.5),
-        (-0.5, -0.5, -0.5),
-        (0.5, -0.5, -0.5),
-        (0.5, 0.5, -0.5),
-        (0.5, -0.5, -0.5),
-        (-0.5, -0.5, -0.5),
-        (-0.5, -0.5, -0.5),
-        (-0.5, 0.5, 0.5),
-        (-0.5, 0.5, -0.5),
-        (0.5, -0.5, 0.5),
-        (-0.5, -0.5, 0.5),
-        (-0.5, -0.5, -0.5),
-        (-0.5, 0.5, 0.5),
-        (-0.5, -0.5, 0.5),
-        (0.5, -0.5, 0.5),
-        (0.5, 0.5, 0.5),
-        (0.5, -0.5, -0.5),
-        (0.5, 0.5, -0.5),
-        (0.5, -0.5, -0.5),
-        (0.5, 0.5, 0.5),
-        (0.5, -0.5, 0.5),
-        (0.5, 0.5, 0.5),
-        (0.5, 0.5, -0.5),
-        (-0.5, 0.5, -0.5),
-        (0.5, 0.5, 0.5),
-        (-0.5, 0.5, -0.5),
-        (-0.5, 0.5, 0.5),
-        (0.5, 0.5, 0.5),
-        (-0.5, 0.5, 0.5),
-        (0.5, -0.5, 0.5)
-);
+/// The models `options` selects, as stored in the file, each with its index in the file and where
+/// its voxel (0, 0, 0) lands, and the palette. Without a layer filter this is just the requested
+/// model at the origin. The models are checked against `options.limits` before they are copied.
+pub(crate) fn selected_models(
+    bytes: &[u8],
+    options: &DotVoxOptions,
+) -> Result<(Vec<(usize, Model, [f32; 3])>, Vec<u32>)> {
+    let data = load(bytes)?;
+    let models: Vec<_> = select(&data, bytes, options)?.into_iter()
+        .map(|(index, model, origin)| (index, model.clone(), origin))
+        .collect();
+    Ok((models, data.palette))
+}
 
+/// Borrows the models `options` selects from `data`, checked against `options.limits`.
+fn select<'a>(
+    data: &'a DotVoxData,
+    bytes: &[u8],
+    options: &DotVoxOptions,
+) -> Result<Vec<(usize, &'a Model, [f32; 3])>> {
+    let find = |index: usize| data.models.get(index).ok_or(VoxError::MissingModel {
+        index,
+        count: data.models.len(),
+    });
+    let selected = match options.layers {
+        None => vec!((options.model, find(options.model)?, [0.0; 3])),
+        Some(ref filter) => {
+            let scene = Scene::parse(bytes)?;
+            let mut selected = Vec::new();
+            for placed in scene.models().iter().filter(|placed| filter.accepts(&scene, placed)) {
+                let model = find(placed.model)?;
+                selected.push((placed.model, model, placed.origin(&model.size)));
+            }
+            selected
+        }
+    };
+    options.limits.check_models(&selected.iter().map(|&(_, model, _)| model).collect::<Vec<_>>())?;
+    Ok(selected)
 }
 
-fn palette_to_rgba(palette: &Vec<u32>, index: usize) -> [f32; 4] {
-    let color = palette.get(index).unwrap_or(&0);
-    let (a, b, g, r) = (color >> 24u32 & 0xFF, color >> 16u32 & 0xFF, color >> 8u32 & 0xFF, color & 0xFF);
-    [
-        r as f32 / 255.0,
-        g as f32 / 255.0,
-        b as f32 / 255.0,
-        a as f32 / 255.0
-    ]
+/// Checks the vertices of meshing `models` into cubes against `options.limits`, before any of
+/// them is allocated.
+pub(crate) fn check_cube_vertices(models: &[Model], options: &DotVoxOptions) -> Result<()> {
+    let voxels: usize = models.iter().map(|model| model.voxels.len()).sum();
+    Ok(options.limits.check_vertices(CUBE_OFFSETS.len() * voxels)?)
 }
 
-fn voxel_to_cube(voxel: &Voxel, palette: &Vec<u32>) -> Vec<PosColor> {
-    let color = palette_to_rgba(palette, voxel.i as usize);
-    CUBE_OFFSETS.iter()
-        .map(|cube_vertex| {
-            let (vx, vy, vz) = cube_vertex;
-            PosColor {
-                position: [
-                    voxel.x as f32 + vx,
-                    voxel.y as f32 + vy,
-                    voxel.z as f32 + vz
-                ],
-                color
+/// Versions of the `.vox` format the importer reads.
+const SUPPORTED_VERSIONS: [u32; 2] = [150, 200];
+
+/// Largest model MagicaVoxel can address along each axis.
+const MAX_MODEL_SIZE: u32 = 256;
+
+/// Parses and validates a `.vox` file.
+pub(crate) fn load(bytes: &[u8]) -> Result<DotVoxData> {
+    let data = dot_vox::load_bytes(bytes).map_err(|e| VoxError::Malformed(e.to_string()))?;
+    validate(&data)?;
+    Ok(data)
+}
+
+/// Checks that a parsed `.vox` file can be meshed: it has models, none larger than MagicaVoxel
+/// allows, and every voxel lies within its model and uses a colour of the palette.
+pub fn validate(data: &DotVoxData) -> ::std::result::Result<(), VoxError> {
+    if !SUPPORTED_VERSIONS.contains(&data.version) {
+        return Err(VoxError::UnsupportedVersion(data.version));
+    }
+    if data.models.is_empty() {
+        return Err(VoxError::NoModels);
+    }
+    for (index, model) in data.models.iter().enumerate() {
+        let size = [model.size.x, model.size.y, model.size.z];
+        if size.iter().any(|&length| length > MAX_MODEL_SIZE) {
+            return Err(VoxError::ModelTooLarge { model: index, size });
+        }
+        for voxel in &model.voxels {
+            let position = [voxel.x, voxel.y, voxel.z];
+            if (0..3).any(|axis| position[axis] as u32 >= size[axis]) {
+                return Err(VoxError::VoxelOutsideModel { model: index, position, size });
+            }
+            if voxel.i as usize >= data.palette.len() {
+                return Err(VoxError::PaletteIndexOutOfRange {
+                    model: index,
+                    index: voxel.i,
+                    palette_size: data.palette.len(),
+                });
             }
+        }
+    }
+    Ok(())
+}
+
+/// Removes marker voxels and downsamples the model when a LOD is requested.
+pub(crate) fn prepare(mut model: Model, options: &DotVoxOptions) -> Model {
+    if !options.markers.is_empty() {
+        model.voxels.retain(|voxel| !options.markers.contains(&voxel.i));
+    }
+    match options.lod {
+        Some(lod) => lod::downsample(&model, lod.factor, lod.voting),
+        None => model,
+    }
+}
+
+/// Moves vertices built from a `prepare`d model into the space of the full detail model.
+pub(crate) fn to_source_space(vertices: &mut [PosColor], options: &DotVoxOptions) {
+    if let Some(lod) = options.lod {
+        lod::scale_to_source(vertices, lod.factor);
+    }
+}
+
+/// Meshes the models `options` selects, each moved to where the scene places it.
+pub(crate) fn mesh(bytes: &[u8], options: &DotVoxOptions) -> Result<Vec<PosColor>> {
+    let meshed = mesh_timed(bytes, options)?;
+    if log_enabled!(Level::Debug) {
+        debug!("Imported .vox file: {:?}", meshed.stats());
+    }
+    Ok(meshed.vertices)
+}
+
+/// Meshes a `.vox` file like `DotVoxFormat`, also reporting what the import produced and how
+/// long it took. `options.cache_dir` is ignored, the file is always meshed.
+pub fn import_with_stats(
+    bytes: &[u8],
+    options: &DotVoxOptions,
+) -> Result<(Vec<PosColor>, ImportStats)> {
+    let meshed = mesh_timed(bytes, options)?;
+    let stats = meshed.stats();
+    Ok((meshed.vertices, stats))
+}
+
+/// A mesh along with what went into it.
+struct Meshed {
+    vertices: Vec<PosColor>,
+    /// File index and vertex count of each model, in the order they were meshed.
+    sub_meshes: Vec<(u32, usize)>,
+    models: Vec<Model>,
+    parse_time: Duration,
+    mesh_time: Duration,
+}
+
+impl Meshed {
+    fn stats(&self) -> ImportStats {
+        ImportStats::measure(&self.models, &self.vertices, self.parse_time, self.mesh_time)
+    }
+}
+
+fn mesh_timed(bytes: &[u8], options: &DotVoxOptions) -> Result<Meshed> {
+    let started = Instant::now();
+    let (models, palette) = selected_models(bytes, options)?;
+    let (models, placements): (Vec<_>, Vec<_>) = models.into_iter()
+        .map(|(index, model, origin)| (prepare(model, options), (index, origin)))
+        .unzip();
+    if options.extractor == Extractor::Cubes {
+        check_cube_vertices(&models, options)?;
+    }
+    let parsed = Instant::now();
+
+    let mut vertices = Vec::new();
+    let mut sub_meshes = Vec::with_capacity(models.len());
+    for (model, (index, origin)) in models.iter().zip(placements) {
+        let mut model_vertices = extract(model, &palette, options);
+        for vertex in &mut model_vertices {
+            for axis in 0..3 {
+                vertex.position[axis] += origin[axis];
+            }
+        }
+        sub_meshes.push((index as u32, model_vertices.len()));
+        vertices.extend(model_vertices);
+    }
+    if let Some(ref shading) = options.shading {
+        shading::shade_faces(&mut vertices, shading);
+    }
+    if options.extractor == Extractor::SurfaceNets {
+        options.limits.check_vertices(vertices.len())?;
+    }
+    Ok(Meshed {
+        vertices,
+        sub_meshes,
+        models,
+        parse_time: parsed - started,
+        mesh_time: parsed.elapsed(),
+    })
+}
+
+/// Turns a `prepare`d model into triangles with the chosen extractor, in the space of
+/// the full detail model.
+fn extract(model: &Model, palette: &[u32], options: &DotVoxOptions) -> Vec<PosColor> {
+    let mut vertices = match options.extractor {
+        #[cfg(feature = "parallel")]
+        Extractor::Cubes => mesh_model_parallel(model, palette),
+        #[cfg(not(feature = "parallel"))]
+        Extractor::Cubes => mesh_model(model, palette),
+        Extractor::SurfaceNets => surface_nets::surface_nets(model, palette),
+    };
+    to_source_space(&mut vertices, options);
+    vertices
+}
+
+/// Number of z layers meshed together as a single unit of work.
+const SLAB_DEPTH: usize = 16;
+
+/// Meshes a model into a triangle list, one cube per voxel.
+pub fn mesh_model(model: &Model, palette: &[u32]) -> Vec<PosColor> {
+    slabs(model).iter()
+        .flat_map(|slab| mesh_slab(slab, palette))
+        .collect()
+}
+
+/// Meshes a grid into a triangle list like `mesh_model`, for voxels that never came from a `.vox`
+/// file, e.g. ones filled by `fill_terrain`. Grids may be larger than models. Load the vertices
+/// with `Loader::load_from_data` as `MeshData::PosColor` to draw them with `DrawVoxels`.
+pub fn mesh_grid<G: VoxelGrid>(grid: &G, palette: &[u32]) -> Vec<PosColor> {
+    grid.voxels()
+        .flat_map(|(voxel, index)| voxel_to_cube(voxel, index, palette))
+        .collect()
+}
+
+/// Meshes a model like `mesh_model`, processing slabs of the volume on the rayon thread pool.
+/// The output is identical to `mesh_model`.
+#[cfg(feature = "parallel")]
+pub fn mesh_model_parallel(model: &Model, palette: &[u32]) -> Vec<PosColor> {
+    use rayon::prelude::*;
+    slabs(model).par_iter()
+        .map(|slab| mesh_slab(slab, palette))
+        .collect::<Vec<_>>()
+        .concat()
+}
+
+/// Splits the voxels of a model into slabs of `SLAB_DEPTH` z layers, keeping file order within
+/// each slab.
+fn slabs(model: &Model) -> Vec<Vec<&Voxel>> {
+    let count = model.voxels.iter()
+        .map(|voxel| voxel.z as usize / SLAB_DEPTH + 1)
+        .max()
+        .unwrap_or(0);
+    let mut slabs = vec!(Vec::new(); count);
+    for voxel in &model.voxels {
+        slabs[voxel.z as usize / SLAB_DEPTH].push(voxel);
+    }
+    slabs
+}
+
+fn mesh_slab(slab: &[&Voxel], palette: &[u32]) -> Vec<PosColor> {
+    slab.iter()
+        .flat_map(|voxel| {
+            voxel_to_cube([voxel.x as u32, voxel.y as u32, voxel.z as u32], voxel.i, palette)
+        })
+        .collect()
+}
+
+fn voxel_to_cube(voxel: VoxelPosition, index: u8, palette: &[u32]) -> Vec<PosColor> {
+    let color = palette_to_rgba(palette, index as usize);
+    CUBE_OFFSETS.iter()
+        .map(|&(vx, vy, vz)| PosColor {
+            position: [voxel[0] as f32 + vx, voxel[1] as f32 + vy, voxel[2] as f32 + vz],
+            color,
         })
         .collect()
 }
 
 #[cfg(test)]
 mod tests {
+    use amethyst_assets::{Asset, AssetStorage, Handle, HotReloadStrategy, HotReloadSystem, Loader,
+                          ProcessingState, Source};
+    use amethyst_core::rayon::ThreadPoolBuilder;
+    use amethyst_core::timing::Time;
     use avow::vec;
+    use dot_vox::Size;
+    use generate::fill_sphere;
+    use grid::{to_model, DenseGrid};
+    use mesh_cache::SubMesh;
+    use scene::tests::SceneBuilder;
+    use specs::{RunNow, VecStorage, World};
     use super::*;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
 
     const PLACEHOLDER: &'static [u8] = include_bytes!("../resources/mesh/placeholder.vox");
 
@@ -107,6 +414,46 @@ mod tests {
         PosColor { position, color }
     }
 
+    /// Source serving a single in-memory file, standing in for an artist saving it to disk.
+    #[derive(Clone)]
+    struct MemorySource {
+        file: Arc<Mutex<(Vec<u8>, u64)>>,
+    }
+
+    impl MemorySource {
+        fn save(&self, bytes: Vec<u8>) {
+            let mut file = self.file.lock().unwrap();
+            file.0 = bytes;
+            file.1 += 1;
+        }
+    }
+
+    impl Source for MemorySource {
+        fn modified(&self, _path: &str) -> Result<u64> {
+            Ok(self.file.lock().unwrap().1)
+        }
+
+        fn load(&self, _path: &str) -> Result<Vec<u8>> {
+            Ok(self.file.lock().unwrap().0.clone())
+        }
+    }
+
+    fn single_voxel_file() -> Vec<u8> {
+        fn chunk(out: &mut Vec<u8>, id: &[u8], content: &[u32]) {
+            out.extend_from_slice(id);
+            for value in [content.len() as u32 * 4, 0].iter().chain(content.iter()) {
+                out.extend((0..4).map(|shift| (value >> (shift * 8)) as u8));
+            }
+        }
+        let mut children = Vec::new();
+        chunk(&mut children, b"SIZE", &[1, 1, 1]);
+        chunk(&mut children, b"XYZI", &[1, 216 << 24]);
+        let mut file = b"VOX \x96\0\0\0MAIN\0\0\0\0".to_vec();
+        file.extend((0..4).map(|shift| (children.len() >> (shift * 8)) as u8));
+        file.extend(children);
+        file
+    }
+
     #[test]
     fn can_import_a_dot_vox_file() {
         let format = DotVoxFormat;
@@ -114,7 +461,7 @@ mod tests {
         let green = [0.0, 0.93333334, 0.0, 1.0];
         let blue = [0.0, 0.0, 0.93333334, 1.0];
         let yellow = [1.0, 1.0, 0.0, 1.0];
-        match format.import(PLACEHOLDER.to_vec(), ()).unwrap() {
+        match format.import(PLACEHOLDER.to_vec(), Default::default()).unwrap() {
             MeshData::PosColor(result) =>
                 vec::are_eq(result, vec!(pos_color([-0.5, -0.5, -0.5], green),
                                            pos_color([-0.5, -0.5, 0.5], green),
@@ -269,8 +616,7 @@ mod tests {
 
     #[test]
     fn one_voxel_becomes_a_12_triangle_cube() {
-        let result = voxel_to_cube(&Voxel { x: 1, y: 1, z: 1, i: 0 },
-                                   &dot_vox::DEFAULT_PALETTE.to_vec());
+        let result = voxel_to_cube([1, 1, 1], 0, &dot_vox::DEFAULT_PALETTE.to_vec());
         vec::are_eq(result, vec!(pos(0.5, 0.5, 0.5),
                                  pos(0.5, 0.5, 1.5),
                                  pos(0.5, 1.5, 1.5),
@@ -309,9 +655,415 @@ mod tests {
                                  pos(1.5, 0.5, 1.5)));
     }
 
+    #[test]
+    fn import_reuses_an_up_to_date_mesh_cache() {
+        let dir = ::std::env::temp_dir().join("dot_vox_amethyst_import_reuses_cache");
+        let _ = ::std::fs::remove_dir_all(&dir);
+        let options = DotVoxOptions { cache_dir: Some(dir.clone()), ..Default::default() };
+        let key = options.cache_key(PLACEHOLDER);
+
+        let imported = match DotVoxFormat.import(PLACEHOLDER.to_vec(), options.clone()).unwrap() {
+            MeshData::PosColor(vertices) => vertices,
+            result => panic!("Expected miracle, received {:?}", result)
+        };
+        let cached = mesh_cache::read_cache(&dir, key).unwrap();
+        vec::are_eq(cached.to_triangles(), imported);
+
+        let marker = vec!(pos(0.0, 0.0, 0.0), pos(1.0, 0.0, 0.0), pos(0.0, 1.0, 0.0));
+        mesh_cache::write_cache(&dir, &MeshCache::from_triangles(key, &[(0, 3)], &marker));
+        match DotVoxFormat.import(PLACEHOLDER.to_vec(), options).unwrap() {
+            MeshData::PosColor(result) => vec::are_eq(result, marker),
+            result => panic!("Expected miracle, received {:?}", result)
+        }
+        let _ = ::std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn the_mesh_cache_records_a_sub_mesh_per_model() {
+        let dir = ::std::env::temp_dir().join("dot_vox_amethyst_cache_sub_meshes");
+        let _ = ::std::fs::remove_dir_all(&dir);
+        let bytes = SceneBuilder::new()
+            .model(1, 0, [0, 0, 0], false)
+            .model(2, 0, [4, 0, 0], false)
+            .build();
+        let cached = |options: DotVoxOptions| {
+            let options = DotVoxOptions { cache_dir: Some(dir.clone()), ..options };
+            DotVoxFormat.import(bytes.clone(), options.clone()).unwrap();
+            mesh_cache::read_cache(&dir, options.cache_key(&bytes)).unwrap().sub_meshes
+        };
+
+        assert_eq!(cached(DotVoxOptions { model: 1, ..Default::default() }),
+                   vec!(SubMesh { model: 1, start: 0, count: 36 }));
+        assert_eq!(cached(DotVoxOptions { layers: Some(LayerFilter::All), ..Default::default() }),
+                   vec!(SubMesh { model: 0, start: 0, count: 36 },
+                        SubMesh { model: 1, start: 36, count: 36 }));
+        let _ = ::std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn cache_keys_cover_the_options_that_change_the_mesh() {
+        let key = |options: DotVoxOptions| options.cache_key(PLACEHOLDER);
+        let default = key(DotVoxOptions::default());
+        assert_eq!(key(DotVoxOptions { cache_dir: Some("cache".into()), ..Default::default() }),
+                   default);
+        let limits = ImportLimits { max_voxels: Some(10), ..Default::default() };
+        assert_eq!(key(DotVoxOptions { limits, ..Default::default() }), default);
+
+        let changed = vec!(
+            DotVoxOptions { model: 1, ..Default::default() },
+            DotVoxOptions { lod: Some(Lod { factor: 2, voting: LodVoting::Majority }),
+                            ..Default::default() },
+            DotVoxOptions { shading: Some(FaceShading::default()), ..Default::default() },
+            DotVoxOptions { extractor: Extractor::SurfaceNets, ..Default::default() },
+            DotVoxOptions { layers: Some(LayerFilter::All), ..Default::default() },
+            DotVoxOptions { markers: vec!(3), ..Default::default() },
+        );
+        let mut keys: Vec<u64> = changed.into_iter().map(key).collect();
+        keys.push(default);
+        keys.sort();
+        keys.dedup();
+        assert_eq!(keys.len(), 7);
+    }
+
+    #[test]
+    fn slabs_are_meshed_in_z_order() {
+        let voxel = |z, i| Voxel { x: 0, y: 0, z, i };
+        let model = Model {
+            size: dot_vox::Size { x: 1, y: 1, z: 40 },
+            voxels: vec!(voxel(39, 1), voxel(0, 2), voxel(17, 3), voxel(1, 4)),
+        };
+        let palette = dot_vox::DEFAULT_PALETTE.to_vec();
+        let expected = [voxel(0, 2), voxel(1, 4), voxel(17, 3), voxel(39, 1)].iter()
+            .flat_map(|voxel| voxel_to_cube([0, 0, voxel.z as u32], voxel.i, &palette))
+            .collect();
+        vec::are_eq(mesh_model(&model, &palette), expected);
+    }
+
+    #[test]
+    fn lod_import_covers_the_same_space_as_the_full_model() {
+        let options = DotVoxOptions {
+            lod: Some(Lod { factor: 2, voting: lod::LodVoting::AnySolid }),
+            ..Default::default()
+        };
+        match DotVoxFormat.import(PLACEHOLDER.to_vec(), options).unwrap() {
+            MeshData::PosColor(result) => {
+                assert_eq!(result.len(), 36);
+                for vertex in result {
+                    for coordinate in vertex.position.iter() {
+                        assert!(*coordinate == -0.5 || *coordinate == 1.5);
+                    }
+                }
+            }
+            result => panic!("Expected miracle, received {:?}", result)
+        }
+    }
+
+    #[test]
+    fn layer_filter_imports_the_matching_models_where_the_scene_places_them() {
+        let bytes = SceneBuilder::new()
+            .layer("intact", false)
+            .layer("damaged", true)
+            .model(1, 0, [0, 0, 0], false)
+            .model(2, 1, [4, 0, 0], false)
+            .build();
+        let named = |names: &[&str]| DotVoxOptions {
+            layers: Some(LayerFilter::Named(names.iter().map(|name| name.to_string()).collect())),
+            ..Default::default()
+        };
+
+        let damaged = mesh(&bytes, &named(&["damaged"])).unwrap();
+        assert_eq!(damaged.len(), 36);
+        assert!(damaged.iter().all(|vertex| (vertex.position[0] - 4.0).abs() == 0.5));
+
+        assert_eq!(mesh(&bytes, &named(&["intact", "damaged"])).unwrap().len(), 72);
+        assert_eq!(mesh(&bytes, &named(&["missing"])).unwrap().len(), 0);
+        let visible = DotVoxOptions { layers: Some(LayerFilter::Visible), ..Default::default() };
+        assert_eq!(mesh(&bytes, &visible).unwrap().len(), 36);
+    }
+
+    #[test]
+    fn any_model_of_a_file_can_be_imported() {
+        let bytes = SceneBuilder::new()
+            .model(1, 0, [0; 3], false)
+            .model(215, 0, [0; 3], false)
+            .build();
+        let second = mesh(&bytes, &DotVoxOptions { model: 1, ..Default::default() }).unwrap();
+        assert_eq!(second[0].color, [0.93333334, 0.0, 0.0, 1.0]);
+        assert!(mesh(&bytes, &DotVoxOptions { model: 2, ..Default::default() }).is_err());
+    }
+
+    #[test]
+    fn face_shading_darkens_everything_but_the_top() {
+        let options = DotVoxOptions { shading: Some(FaceShading::default()), ..Default::default() };
+        let plain = mesh(&single_voxel_file(), &DotVoxOptions::default()).unwrap();
+        let shaded = mesh(&single_voxel_file(), &options).unwrap();
+        for (plain, shaded) in plain.chunks(3).zip(shaded.chunks(3)) {
+            let on_top = plain.iter().all(|vertex| vertex.position[2] == 0.5);
+            assert_eq!(shaded[0].color == plain[0].color, on_top);
+            assert_eq!(shaded[0].color[3], plain[0].color[3]);
+        }
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_meshing_matches_sequential_meshing() {
+        let model = dot_vox::load_bytes(PLACEHOLDER).unwrap().models.remove(0);
+        let palette = dot_vox::DEFAULT_PALETTE.to_vec();
+        vec::are_eq(mesh_model_parallel(&model, &palette), mesh_model(&model, &palette));
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn parallel_meshing_keeps_the_order_of_many_slabs() {
+        let mut grid = DenseGrid::new([40, 40, 40]);
+        fill_sphere(&mut grid, [20.0, 20.0, 20.0], 19.0, 3);
+        let mut model = to_model(&grid).unwrap();
+        model.voxels.reverse();
+        let palette = dot_vox::DEFAULT_PALETTE.to_vec();
+        assert_eq!(slabs(&model).len(), 3);
+        vec::are_eq(mesh_model_parallel(&model, &palette), mesh_model(&model, &palette));
+    }
+
     #[test]
     fn palette_and_index_becomes_a_color() {
         assert_eq!(palette_to_rgba(&dot_vox::DEFAULT_PALETTE.to_vec(), 215),
                    [0.93333334, 0.0, 0.0, 1.0]);
     }
+
+    /// Stands in for `Mesh` in the asset storage, as building a real mesh needs a GPU factory:
+    /// it holds the vertices the renderer would upload.
+    struct Vertices(Vec<PosColor>);
+
+    impl Asset for Vertices {
+        const NAME: &'static str = "dot_vox_amethyst::Vertices";
+        type Data = MeshData;
+        type HandleStorage = VecStorage<Handle<Self>>;
+    }
+
+    /// `DotVoxFormat` loading into `Vertices`.
+    #[derive(Clone)]
+    struct VerticesFormat;
+
+    impl SimpleFormat<Vertices> for VerticesFormat {
+        const NAME: &'static str = "MAGICAVOXEL_DOT_VOX";
+        type Options = DotVoxOptions;
+
+        fn import(&self, bytes: Vec<u8>, options: DotVoxOptions) -> Result<MeshData> {
+            DotVoxFormat.import(bytes, options)
+        }
+    }
+
+    fn to_vertices(data: MeshData) -> Result<ProcessingState<Vertices>> {
+        match data {
+            MeshData::PosColor(vertices) => Ok(ProcessingState::Loaded(Vertices(vertices))),
+            _ => Err("Expected PosColor vertices".into()),
+        }
+    }
+
+    #[test]
+    fn saving_the_source_file_reloads_the_mesh() {
+        let source = MemorySource { file: Arc::new(Mutex::new((PLACEHOLDER.to_vec(), 1))) };
+        let pool = Arc::new(ThreadPoolBuilder::new().num_threads(1).build().unwrap());
+        let mut loader = Loader::new(".", pool.clone());
+        loader.set_hot_reload(true);
+        loader.add_source("memory", source.clone());
+        let mut world = World::new();
+        world.add_resource(loader);
+        world.add_resource(AssetStorage::<Vertices>::new());
+        world.add_resource(Time::default());
+        let mut hot_reload = HotReloadSystem::new(HotReloadStrategy::when_triggered());
+        RunNow::setup(&mut hot_reload, &mut world.res);
+
+        let handle = {
+            let loader = world.read_resource::<Loader>();
+            let storage = world.read_resource::<AssetStorage<Vertices>>();
+            let options = DotVoxOptions::default();
+            loader.load_from("placeholder.vox", VerticesFormat, options, "memory", (), &storage)
+        };
+        // Runs frames, asking for a reload in each, until the handle resolves to `count` vertices.
+        let mut run_until = |world: &mut World, count: usize| {
+            for _ in 0..1000 {
+                world.write_resource::<HotReloadStrategy>().trigger();
+                hot_reload.run_now(&world.res);
+                world.write_resource::<Time>().increment_frame_number();
+                let frame = world.read_resource::<Time>().frame_number();
+                let strategy = world.read_resource::<HotReloadStrategy>();
+                let mut storage = world.write_resource::<AssetStorage<Vertices>>();
+                storage.process(to_vertices, frame, &pool, Some(&strategy));
+                if storage.get(&handle).map(|mesh| mesh.0.len()) == Some(count) {
+                    return storage.get(&handle).unwrap().0.clone();
+                }
+                thread::sleep(Duration::from_millis(5));
+            }
+            panic!("The mesh never got {} vertices", count);
+        };
+
+        assert_eq!(run_until(&mut world, 4 * 36).len(), 4 * 36);
+        source.save(single_voxel_file());
+        vec::are_eq(run_until(&mut world, 36),
+                    voxel_to_cube([0, 0, 0], 215, &dot_vox::DEFAULT_PALETTE.to_vec()));
+    }
+
+    fn model(size: [u32; 3], voxels: Vec<Voxel>) -> DotVoxData {
+        DotVoxData {
+            version: 150,
+            models: vec!(Model { size: Size { x: size[0], y: size[1], z: size[2] }, voxels }),
+            palette: dot_vox::DEFAULT_PALETTE.to_vec(),
+        }
+    }
+
+    /// Name of the `VoxError` variant chained into a failed import, `None` if it succeeded.
+    fn vox_error<T>(result: Result<T>) -> Option<&'static str> {
+        let error = format!("{:?}", result.err()?);
+        let variants = ["Malformed", "UnsupportedVersion", "NoModels", "MissingModel",
+                        "ModelTooLarge", "VoxelOutsideModel", "PaletteIndexOutOfRange",
+                        "OverBudget"];
+        let variant = variants.iter().find(|variant| error.contains(*variant));
+        Some(variant.cloned().unwrap_or_else(|| panic!("Not a VoxError: {}", error)))
+    }
+
+    #[test]
+    fn malformed_models_are_rejected_with_the_reason() {
+        let voxel = |x, i| Voxel { x, y: 0, z: 0, i };
+        assert_eq!(validate(&model([2, 1, 1], vec!(voxel(1, 0)))), Ok(()));
+        assert_eq!(
+            validate(&model([2, 1, 1], vec!(voxel(2, 0)))),
+            Err(VoxError::VoxelOutsideModel { model: 0, position: [2, 0, 0], size: [2, 1, 1] })
+        );
+        assert_eq!(validate(&model([257, 1, 1], vec!())),
+                   Err(VoxError::ModelTooLarge { model: 0, size: [257, 1, 1] }));
+
+        let mut short_palette = model([1, 1, 1], vec!(voxel(0, 20)));
+        short_palette.palette.truncate(16);
+        assert_eq!(validate(&short_palette),
+                   Err(VoxError::PaletteIndexOutOfRange { model: 0, index: 20, palette_size: 16 }));
+
+        let mut empty = model([1, 1, 1], vec!());
+        empty.models.clear();
+        assert_eq!(validate(&empty), Err(VoxError::NoModels));
+        empty.version = 7;
+        assert_eq!(validate(&empty), Err(VoxError::UnsupportedVersion(7)));
+    }
+
+    #[test]
+    fn truncated_files_fail_to_import_without_panicking() {
+        let scene = SceneBuilder::new().layer("", false).model(1, 0, [0; 3], false).build();
+        let layered = DotVoxOptions { layers: Some(LayerFilter::All), ..Default::default() };
+        for len in 0..scene.len() {
+            // Cutting the file between two chunks leaves a shorter, valid file, which may have
+            // lost its models, but the scene chunks always end up cut short.
+            let error = vox_error(mesh(&scene[..len], &DotVoxOptions::default()));
+            assert!(error.map_or(true, |error| error == "Malformed" || error == "NoModels"));
+            let error = vox_error(mesh(&scene[..len], &layered));
+            assert!(error == Some("Malformed") || error == Some("NoModels"), "{:?}", error);
+        }
+        for len in (0..PLACEHOLDER.len()).step_by(97) {
+            assert_eq!(vox_error(mesh(&PLACEHOLDER[..len], &layered)), Some("Malformed"));
+        }
+        assert_eq!(vox_error(mesh(&[], &DotVoxOptions::default())), Some("Malformed"));
+    }
+
+    #[test]
+    fn corrupted_files_fail_to_import_without_panicking() {
+        let scene = SceneBuilder::new()
+            .layer("", false)
+            .model(1, 0, [0; 3], false)
+            .named("corner")
+            .model(2, 0, [4, 0, 0], false)
+            .build();
+        let layered = DotVoxOptions { layers: Some(LayerFilter::All), ..Default::default() };
+        // xorshift, so every run corrupts the same bytes.
+        let mut state = 0x2545_f491u32;
+        let mut random = move || {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state
+        };
+        for _ in 0..2000 {
+            let mut bytes = scene.clone();
+            for _ in 0..1 + random() % 4 {
+                let at = random() as usize % bytes.len();
+                bytes[at] = random() as u8;
+            }
+            // Whatever the damage, it is reported as a `VoxError`.
+            vox_error(mesh(&bytes, &DotVoxOptions::default()));
+            vox_error(mesh(&bytes, &layered));
+        }
+
+        let mut bytes = scene.clone();
+        bytes[0] = b'X';
+        assert_eq!(vox_error(mesh(&bytes, &layered)), Some("Malformed"));
+        let mut bytes = scene.clone();
+        bytes[4] = 1;
+        assert_eq!(vox_error(mesh(&bytes, &layered)), Some("UnsupportedVersion"));
+    }
+
+    #[test]
+    fn validation_errors_reach_the_asset_error() {
+        let mut bytes = single_voxel_file();
+        bytes[4] = 1;
+        let error = DotVoxFormat.import(bytes, DotVoxOptions::default()).unwrap_err();
+        assert!(format!("{:?}", error).contains("UnsupportedVersion(1)"));
+    }
+
+    #[test]
+    fn imports_over_their_limits_are_rejected() {
+        let limits = ImportLimits { max_voxels: Some(3), ..Default::default() };
+        let options = DotVoxOptions { limits, ..Default::default() };
+        assert_eq!(mesh(PLACEHOLDER, &DotVoxOptions::default()).unwrap().len(), 4 * 36);
+        assert!(mesh(PLACEHOLDER, &options).is_err());
+        assert!(selected_models(PLACEHOLDER, &options).is_err());
+
+        // Voxels are counted in the file, before downsampling would have brought them in budget.
+        let lod = Some(Lod { factor: 8, voting: LodVoting::AnySolid });
+        assert!(mesh(PLACEHOLDER, &DotVoxOptions { lod, ..options.clone() }).is_err());
+
+        // Vertices are counted in the finished mesh.
+        let vertices = |max| DotVoxOptions {
+            limits: ImportLimits { max_vertices: Some(max), ..Default::default() },
+            ..Default::default()
+        };
+        assert!(mesh(PLACEHOLDER, &vertices(4 * 36)).is_ok());
+        assert!(mesh(PLACEHOLDER, &vertices(4 * 36 - 1)).is_err());
+        assert!(mesh(PLACEHOLDER, &DotVoxOptions { lod, ..vertices(36) }).is_ok());
+    }
+
+    #[test]
+    fn cached_imports_are_held_to_their_limits() {
+        let dir = ::std::env::temp_dir().join("dot_vox_amethyst_cached_limits");
+        let _ = ::std::fs::remove_dir_all(&dir);
+        let options = |limits| DotVoxOptions {
+            cache_dir: Some(dir.clone()),
+            limits,
+            ..Default::default()
+        };
+        assert!(DotVoxFormat.import(PLACEHOLDER.to_vec(), options(Default::default())).is_ok());
+        assert!(mesh_cache::read_cache(&dir, options(Default::default()).cache_key(PLACEHOLDER))
+            .is_some());
+
+        let voxels = ImportLimits { max_voxels: Some(3), ..Default::default() };
+        assert!(DotVoxFormat.import(PLACEHOLDER.to_vec(), options(voxels)).is_err());
+        let vertices = ImportLimits { max_vertices: Some(4 * 36 - 1), ..Default::default() };
+        assert!(DotVoxFormat.import(PLACEHOLDER.to_vec(), options(vertices)).is_err());
+        let _ = ::std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn stats_describe_the_imported_mesh() {
+        let (vertices, stats) = import_with_stats(PLACEHOLDER, &DotVoxOptions::default()).unwrap();
+        assert_eq!(vertices, mesh(PLACEHOLDER, &DotVoxOptions::default()).unwrap());
+        assert_eq!(stats.voxels, 4);
+        assert_eq!(stats.indices, vertices.len());
+        assert_eq!(stats.faces_emitted, 4 * 6);
+    }
+
+    #[test]
+    fn generated_grids_mesh_like_the_equivalent_model() {
+        let mut grid = DenseGrid::new([8, 8, 8]);
+        fill_sphere(&mut grid, [4.0, 4.0, 4.0], 3.0, 1);
+        let palette = [0xFF00_00FF, 0xFF00_FF00];
+        let model = to_model(&grid).unwrap();
+        assert_eq!(mesh_grid(&grid, &palette), mesh_model(&model, &palette));
+    }
 }
\ No newline at end of file
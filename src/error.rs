@@ -0,0 +1,70 @@
+#[cfg(feature = "amethyst")]
+use amethyst_assets::Error;
+use limits::Budget;
+
+use std::error;
+use std::fmt;
+
+/// Why a `.vox` file was rejected. Formats return it chained into an `amethyst_assets::Error`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum VoxError {
+    /// The file could not be parsed at all, e.g. because it is truncated.
+    Malformed(String),
+    /// The file was written by a MagicaVoxel version this crate can't read.
+    UnsupportedVersion(u32),
+    /// The file holds no models.
+    NoModels,
+    /// A model was requested, or referenced by the scene, that the file doesn't have.
+    MissingModel { index: usize, count: usize },
+    /// A model is larger than the 256 voxels along each axis MagicaVoxel can address.
+    ModelTooLarge { model: usize, size: [u32; 3] },
+    /// A voxel lies outside the size its model declares.
+    VoxelOutsideModel { model: usize, position: [u8; 3], size: [u32; 3] },
+    /// A voxel refers to a colour past the end of the palette.
+    PaletteIndexOutOfRange { model: usize, index: u8, palette_size: usize },
+    /// The import goes over one of the `ImportLimits` in its options.
+    OverBudget { budget: Budget, count: usize, limit: usize },
+}
+
+impl fmt::Display for VoxError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            VoxError::Malformed(ref reason) => write!(f, "Malformed .vox file: {}", reason),
+            VoxError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported .vox version {}", version)
+            }
+            VoxError::NoModels => write!(f, ".vox file holds no models"),
+            VoxError::MissingModel { index, count } => {
+                write!(f, "Model {} requested, but the file has {} models", index, count)
+            }
+            VoxError::ModelTooLarge { model, size } => {
+                write!(f, "Model {} is {}x{}x{}, larger than 256 along an axis",
+                       model, size[0], size[1], size[2])
+            }
+            VoxError::VoxelOutsideModel { model, position, size } => {
+                write!(f, "Voxel {:?} of model {} lies outside its size {:?}",
+                       position, model, size)
+            }
+            VoxError::PaletteIndexOutOfRange { model, index, palette_size } => {
+                write!(f, "Voxel of model {} uses palette index {}, but the palette has {} colours",
+                       model, index, palette_size)
+            }
+            VoxError::OverBudget { budget, count, limit } => {
+                write!(f, "Import has {} {:?}, over the limit of {}", count, budget, limit)
+            }
+        }
+    }
+}
+
+impl error::Error for VoxError {
+    fn description(&self) -> &str {
+        "Invalid .vox file"
+    }
+}
+
+#[cfg(feature = "amethyst")]
+impl From<VoxError> for Error {
+    fn from(error: VoxError) -> Error {
+        Error::with_chain(error, "Invalid .vox file")
+    }
+}
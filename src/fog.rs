@@ -0,0 +1,106 @@
+use amethyst_core::GlobalTransform;
+use amethyst_renderer::Camera;
+
+/// How quickly fog thickens past `VoxelFog::start`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FogFalloff {
+    /// Visibility drops linearly, reaching zero `1 / density` units past the start.
+    Linear,
+    /// Visibility decays exponentially with `density`.
+    Exponential,
+}
+
+/// Resource fading voxels into `color` with their distance from the camera. Voxel passes draw
+/// without fog while the resource is absent.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VoxelFog {
+    pub falloff: FogFalloff,
+    /// Colour voxels fade into, usually the clear colour so distant models blend into the sky.
+    pub color: [f32; 4],
+    /// Distance from the camera at which fog begins.
+    pub start: f32,
+    pub density: f32,
+}
+
+impl VoxelFog {
+    /// Share of a voxel's own colour left at `distance` from the camera, from 1 when unaffected
+    /// down to 0 when hidden by fog. Matches the fragment shader, so it can be used to skip
+    /// entities that would be invisible anyway.
+    pub fn visibility(&self, distance: f32) -> f32 {
+        let depth = (distance - self.start).max(0.0);
+        let visibility = match self.falloff {
+            FogFalloff::Linear => 1.0 - depth * self.density,
+            FogFalloff::Exponential => (-depth * self.density).exp(),
+        };
+        visibility.max(0.0).min(1.0)
+    }
+}
+
+/// Fog as seen by the current camera, in the form the fragment shader expects. The default
+/// disables fog.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub(crate) struct FogArgs {
+    pub(crate) color: [f32; 4],
+    pub(crate) camera_position: [f32; 3],
+    /// 0 for no fog, otherwise 1 + the `FogFalloff`.
+    pub(crate) falloff: i32,
+    pub(crate) start: f32,
+    pub(crate) density: f32,
+}
+
+impl FogArgs {
+    pub(crate) fn new(fog: Option<&VoxelFog>, camera: Option<(&Camera, &GlobalTransform)>) -> Self {
+        let fog = match fog {
+            Some(fog) => fog,
+            None => return FogArgs::default(),
+        };
+        let camera_position = camera
+            .map(|(_, transform)| transform.0.w.truncate().into())
+            .unwrap_or([0.0; 3]);
+        FogArgs {
+            color: fog.color,
+            camera_position,
+            falloff: match fog.falloff {
+                FogFalloff::Linear => 1,
+                FogFalloff::Exponential => 2,
+            },
+            start: fog.start,
+            density: fog.density,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fog(falloff: FogFalloff) -> VoxelFog {
+        VoxelFog { falloff, color: [0.5; 4], start: 10.0, density: 0.25 }
+    }
+
+    #[test]
+    fn nothing_closer_than_the_start_is_fogged() {
+        assert_eq!(fog(FogFalloff::Linear).visibility(3.0), 1.0);
+        assert_eq!(fog(FogFalloff::Exponential).visibility(10.0), 1.0);
+    }
+
+    #[test]
+    fn linear_fog_hides_everything_one_over_density_past_the_start() {
+        let fog = fog(FogFalloff::Linear);
+        assert_eq!(fog.visibility(12.0), 0.5);
+        assert_eq!(fog.visibility(14.0), 0.0);
+        assert_eq!(fog.visibility(100.0), 0.0);
+    }
+
+    #[test]
+    fn exponential_fog_decays_with_density() {
+        let visibility = fog(FogFalloff::Exponential).visibility(14.0);
+        assert!((visibility - (-1.0f32).exp()).abs() < 1e-6);
+    }
+
+    #[test]
+    fn fog_is_disabled_without_the_resource() {
+        assert_eq!(FogArgs::new(None, None).falloff, 0);
+        assert_eq!(FogArgs::new(Some(&fog(FogFalloff::Exponential)), None).falloff, 2);
+    }
+}
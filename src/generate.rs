@@ -0,0 +1,273 @@
+//! Generators filling a `VoxelGrid` with shapes and terrain, for prototyping without a `.vox`
+//! file. Grids are z-up like MagicaVoxel models, and `mesh_grid` turns them into a mesh for
+//! `DrawVoxels`.
+
+use grid::{VoxelGrid, VoxelPosition};
+
+use std::error;
+use std::fmt;
+use std::str;
+
+/// Fills the cells from `min` up to, but not including, `max` with `index`, clipped to the grid.
+pub fn fill_box<G: VoxelGrid>(grid: &mut G, min: VoxelPosition, max: VoxelPosition, index: u8) {
+    let size = grid.size();
+    for z in min[2]..max[2].min(size[2]) {
+        for y in min[1]..max[1].min(size[1]) {
+            for x in min[0]..max[0].min(size[0]) {
+                grid.set([x, y, z], Some(index));
+            }
+        }
+    }
+}
+
+/// Fills the cells whose centre lies within `radius` of `centre` with `index`.
+pub fn fill_sphere<G: VoxelGrid>(grid: &mut G, centre: [f32; 3], radius: f32, index: u8) {
+    let min = [centre[0] - radius, centre[1] - radius, centre[2] - radius];
+    let max = [centre[0] + radius, centre[1] + radius, centre[2] + radius];
+    fill_where(grid, min, max, index, |cell| {
+        let offset = [cell[0] - centre[0], cell[1] - centre[1], cell[2] - centre[2]];
+        offset[0] * offset[0] + offset[1] * offset[1] + offset[2] * offset[2] <= radius * radius
+    });
+}
+
+/// Fills an upright cylinder with `index`: the cells whose centre lies within `radius` of the
+/// vertical axis through `base`, from `base` up to `height` cells higher.
+pub fn fill_cylinder<G: VoxelGrid>(
+    grid: &mut G,
+    base: [f32; 3],
+    radius: f32,
+    height: f32,
+    index: u8,
+) {
+    let min = [base[0] - radius, base[1] - radius, base[2]];
+    let max = [base[0] + radius, base[1] + radius, base[2] + height];
+    fill_where(grid, min, max, index, |cell| {
+        let offset = [cell[0] - base[0], cell[1] - base[1]];
+        offset[0] * offset[0] + offset[1] * offset[1] <= radius * radius
+    });
+}
+
+/// Fills the cells of the grid within the bounds `min` to `max` whose centre passes `inside`.
+fn fill_where<G, F>(grid: &mut G, min: [f32; 3], max: [f32; 3], index: u8, inside: F)
+where
+    G: VoxelGrid,
+    F: Fn([f32; 3]) -> bool,
+{
+    let size = grid.size();
+    let range = |axis: usize| {
+        let start = min[axis].floor().max(0.0) as u32;
+        let end = (max[axis].ceil().max(0.0) as u32).min(size[axis]);
+        start..end
+    };
+    for z in range(2) {
+        for y in range(1) {
+            for x in range(0) {
+                if inside([x as f32 + 0.5, y as f32 + 0.5, z as f32 + 0.5]) {
+                    grid.set([x, y, z], Some(index));
+                }
+            }
+        }
+    }
+}
+
+/// Heights between 0 and 1 over a `width` x `depth` footprint, for `fill_terrain`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Heightmap {
+    pub width: u32,
+    pub depth: u32,
+    /// One height per column, x varying fastest.
+    pub heights: Vec<f32>,
+}
+
+impl Heightmap {
+    /// Smooth value noise: random heights at the corners of `cell` sized squares, blended in
+    /// between. The same `seed` always gives the same heights.
+    pub fn noise(width: u32, depth: u32, cell: u32, seed: u32) -> Heightmap {
+        let cell = cell.max(1);
+        let corner = |x: u32, y: u32| {
+            let hash = mix(mix(seed ^ 0x9e37_79b9).wrapping_add(x)).wrapping_add(y);
+            (mix(hash) >> 8) as f32 / (1 << 24) as f32
+        };
+        let smooth = |t: f32| t * t * (3.0 - 2.0 * t);
+        let mut heights = Vec::with_capacity(width as usize * depth as usize);
+        for y in 0..depth {
+            for x in 0..width {
+                let (cx, cy) = (x / cell, y / cell);
+                let fx = smooth((x % cell) as f32 / cell as f32);
+                let fy = smooth((y % cell) as f32 / cell as f32);
+                let near = corner(cx, cy) + (corner(cx + 1, cy) - corner(cx, cy)) * fx;
+                let far = corner(cx, cy + 1) + (corner(cx + 1, cy + 1) - corner(cx, cy + 1)) * fx;
+                heights.push(near + (far - near) * fy);
+            }
+        }
+        Heightmap { width, depth, heights }
+    }
+
+    /// Heights from 8 bit grayscale pixels, row by row, white being the highest.
+    pub fn from_gray(width: u32, depth: u32, pixels: &[u8]) -> Result<Heightmap, HeightmapError> {
+        let expected = width as usize * depth as usize;
+        if pixels.len() != expected {
+            return Err(HeightmapError::WrongPixelCount { expected, found: pixels.len() });
+        }
+        let heights = pixels.iter().map(|&pixel| pixel as f32 / 255.0).collect();
+        Ok(Heightmap { width, depth, heights })
+    }
+
+    /// Heights from a binary PGM (`P5`) grayscale image with 8 bit samples, as exported by most
+    /// image editors. Other formats can be decoded elsewhere and handed to `from_gray`.
+    pub fn from_pgm(bytes: &[u8]) -> Result<Heightmap, HeightmapError> {
+        // The header is "P5", width, height and the maximum sample, separated by whitespace and
+        // followed by a single whitespace byte before the pixels.
+        let mut fields = Vec::new();
+        let mut offset = 0;
+        while fields.len() < 4 {
+            while offset < bytes.len() && (bytes[offset] as char).is_whitespace() {
+                offset += 1;
+            }
+            if offset < bytes.len() && bytes[offset] == b'#' {
+                while offset < bytes.len() && bytes[offset] != b'\n' {
+                    offset += 1;
+                }
+                continue;
+            }
+            let start = offset;
+            while offset < bytes.len() && !(bytes[offset] as char).is_whitespace() {
+                offset += 1;
+            }
+            if start == offset {
+                return Err(HeightmapError::NotAPgm);
+            }
+            let field = str::from_utf8(&bytes[start..offset]);
+            fields.push(field.map_err(|_| HeightmapError::NotAPgm)?);
+        }
+        let number = |field: &str| field.parse::<u32>().map_err(|_| HeightmapError::NotAPgm);
+        let (width, depth, max) = (number(fields[1])?, number(fields[2])?, number(fields[3])?);
+        if fields[0] != "P5" || max == 0 || max > 255 {
+            return Err(HeightmapError::NotAPgm);
+        }
+        let pixels = bytes.get(offset + 1..).unwrap_or(&[]);
+        let mut heightmap = Heightmap::from_gray(width, depth, pixels)?;
+        for height in &mut heightmap.heights {
+            *height = (*height * 255.0 / max as f32).min(1.0);
+        }
+        Ok(heightmap)
+    }
+
+    /// Height of the column at `x`, `y`, 0 outside the map.
+    pub fn height(&self, x: u32, y: u32) -> f32 {
+        if x >= self.width || y >= self.depth {
+            return 0.0;
+        }
+        self.heights[y as usize * self.width as usize + x as usize]
+    }
+}
+
+/// Fills each column of the grid up to its height in `heightmap`, scaled to the height of the
+/// grid. The top cell of a column gets `surface`, the ones below `below`, e.g. grass over dirt.
+pub fn fill_terrain<G: VoxelGrid>(grid: &mut G, heightmap: &Heightmap, surface: u8, below: u8) {
+    let size = grid.size();
+    for y in 0..size[1] {
+        for x in 0..size[0] {
+            let height = (heightmap.height(x, y) * size[2] as f32).round() as u32;
+            for z in 0..height.min(size[2]) {
+                grid.set([x, y, z], Some(if z + 1 == height { surface } else { below }));
+            }
+        }
+    }
+}
+
+/// Scrambles the bits of `value`, for noise.
+fn mix(mut value: u32) -> u32 {
+    value ^= value >> 16;
+    value = value.wrapping_mul(0x7feb_352d);
+    value ^= value >> 15;
+    value = value.wrapping_mul(0x846c_a68b);
+    value ^ value >> 16
+}
+
+/// Why a heightmap image could not be read.
+#[derive(Clone, Debug, PartialEq)]
+pub enum HeightmapError {
+    /// The bytes are not a binary PGM image with 8 bit samples.
+    NotAPgm,
+    /// The pixels don't match the size of the image.
+    WrongPixelCount { expected: usize, found: usize },
+}
+
+impl fmt::Display for HeightmapError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            HeightmapError::NotAPgm => write!(f, "Not a binary PGM image with 8 bit samples"),
+            HeightmapError::WrongPixelCount { expected, found } => {
+                write!(f, "Heightmap needs {} pixels, found {}", expected, found)
+            }
+        }
+    }
+}
+
+impl error::Error for HeightmapError {
+    fn description(&self) -> &str {
+        "Invalid heightmap"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use grid::{DenseGrid, SparseGrid};
+
+    #[test]
+    fn shapes_fill_the_cells_inside_them() {
+        let mut grid = SparseGrid::new([10, 10, 10]);
+        fill_box(&mut grid, [1, 2, 3], [3, 4, 20], 1);
+        assert_eq!(grid.len(), 2 * 2 * 7);
+        assert_eq!(grid.bounds(), Some(([1, 2, 3], [2, 3, 9])));
+
+        let mut grid = SparseGrid::new([10, 10, 10]);
+        fill_sphere(&mut grid, [5.0, 5.0, 5.0], 1.0, 2);
+        // Only the 8 cells around the centre have their centre within one cell.
+        assert_eq!(grid.len(), 8);
+        assert_eq!(grid.get([4, 4, 4]), Some(2));
+
+        let mut grid = SparseGrid::new([10, 10, 10]);
+        fill_cylinder(&mut grid, [5.0, 5.0, 2.0], 1.6, 4.0, 3);
+        assert_eq!(grid.len(), 12 * 4);
+        assert_eq!(grid.bounds(), Some(([3, 3, 2], [6, 6, 5])));
+    }
+
+    #[test]
+    fn terrain_follows_the_heightmap() {
+        let heightmap = Heightmap::from_gray(2, 1, &[0, 255]).unwrap();
+        let mut grid = DenseGrid::new([2, 1, 4]);
+        fill_terrain(&mut grid, &heightmap, 1, 2);
+        let mut voxels: Vec<_> = grid.voxels().collect();
+        voxels.sort();
+        assert_eq!(voxels, vec!(([1, 0, 0], 2), ([1, 0, 1], 2), ([1, 0, 2], 2), ([1, 0, 3], 1)));
+    }
+
+    #[test]
+    fn noise_is_smooth_and_repeatable() {
+        let heightmap = Heightmap::noise(64, 64, 16, 7);
+        assert_eq!(heightmap, Heightmap::noise(64, 64, 16, 7));
+        assert_ne!(heightmap, Heightmap::noise(64, 64, 16, 8));
+        assert!(heightmap.heights.iter().all(|&height| height >= 0.0 && height < 1.0));
+        for y in 0..64 {
+            for x in 1..64 {
+                assert!((heightmap.height(x, y) - heightmap.height(x - 1, y)).abs() < 0.2);
+            }
+        }
+    }
+
+    #[test]
+    fn pgm_images_load_as_heightmaps() {
+        let mut bytes = b"P5\n# exported heightmap\n3 1\n100\n".to_vec();
+        bytes.extend_from_slice(&[0, 50, 100]);
+        let heightmap = Heightmap::from_pgm(&bytes).unwrap();
+        assert_eq!((heightmap.width, heightmap.depth), (3, 1));
+        assert_eq!(heightmap.heights, vec!(0.0, 0.5, 1.0));
+
+        assert_eq!(Heightmap::from_pgm(b"P2\n1 1\n255\n0"), Err(HeightmapError::NotAPgm));
+        assert_eq!(Heightmap::from_pgm(b"P5\n2 1\n255\n\x01"),
+                   Err(HeightmapError::WrongPixelCount { expected: 2, found: 1 }));
+    }
+}
@@ -0,0 +1,305 @@
+use dot_vox::{Model, Size, Voxel};
+
+use std::collections::HashMap;
+use std::mem;
+
+/// Position of a cell in a `VoxelGrid`.
+pub type VoxelPosition = [u32; 3];
+
+/// Offsets to the six face neighbours of a cell, in the order `neighbours` returns them: -x, +x,
+/// -y, +y, -z, +z.
+pub const NEIGHBOUR_OFFSETS: [[i32; 3]; 6] = [
+    [-1, 0, 0],
+    [1, 0, 0],
+    [0, -1, 0],
+    [0, 1, 0],
+    [0, 0, -1],
+    [0, 0, 1],
+];
+
+/// A box of cells, each empty or holding a palette index as in `Voxel::i`.
+pub trait VoxelGrid {
+    /// Number of cells along each axis.
+    fn size(&self) -> VoxelPosition;
+
+    /// Palette index of the cell at `position`, `None` when it is empty or outside the grid.
+    fn get(&self, position: VoxelPosition) -> Option<u8>;
+
+    /// Fills the cell at `position`, or empties it with `None`.
+    ///
+    /// # Panics
+    ///
+    /// Panics when `position` lies outside the grid.
+    fn set(&mut self, position: VoxelPosition, index: Option<u8>);
+
+    /// Every filled cell with its palette index.
+    fn voxels<'a>(&'a self) -> Box<dyn Iterator<Item = (VoxelPosition, u8)> + 'a>;
+
+    /// Number of filled cells.
+    fn len(&self) -> usize {
+        self.voxels().count()
+    }
+
+    /// Whether no cell is filled.
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// The cells on the six sides of `position`, in the order of `NEIGHBOUR_OFFSETS`. Cells past
+    /// the edge of the grid are empty.
+    fn neighbours(&self, position: VoxelPosition) -> [Option<u8>; 6] {
+        let mut neighbours = [None; 6];
+        for (neighbour, offset) in neighbours.iter_mut().zip(&NEIGHBOUR_OFFSETS) {
+            *neighbour = offset_position(position, *offset).and_then(|at| self.get(at));
+        }
+        neighbours
+    }
+
+    /// Smallest and largest filled positions, or `None` when the grid is empty.
+    fn bounds(&self) -> Option<(VoxelPosition, VoxelPosition)> {
+        self.voxels().fold(None, |bounds, (position, _)| {
+            let (mut min, mut max) = bounds.unwrap_or((position, position));
+            for axis in 0..3 {
+                min[axis] = min[axis].min(position[axis]);
+                max[axis] = max[axis].max(position[axis]);
+            }
+            Some((min, max))
+        })
+    }
+}
+
+/// `position` moved by `offset`, unless that leaves the positive octant.
+pub fn offset_position(position: VoxelPosition, offset: [i32; 3]) -> Option<VoxelPosition> {
+    let mut moved = [0; 3];
+    for axis in 0..3 {
+        let coordinate = position[axis] as i64 + offset[axis] as i64;
+        if coordinate < 0 || coordinate > u32::max_value() as i64 {
+            return None;
+        }
+        moved[axis] = coordinate as u32;
+    }
+    Some(moved)
+}
+
+/// Converts a grid back into a model, for grids no larger than MagicaVoxel's 256 cells along each
+/// axis.
+pub fn to_model<G: VoxelGrid>(grid: &G) -> Option<Model> {
+    let size = grid.size();
+    if size.iter().any(|&length| length > 256) {
+        return None;
+    }
+    let mut voxels: Vec<Voxel> = grid.voxels()
+        .map(|(position, i)| Voxel {
+            x: position[0] as u8,
+            y: position[1] as u8,
+            z: position[2] as u8,
+            i,
+        })
+        .collect();
+    voxels.sort_by_key(|voxel| (voxel.z, voxel.y, voxel.x));
+    Some(Model { size: Size { x: size[0], y: size[1], z: size[2] }, voxels })
+}
+
+fn contains(size: VoxelPosition, position: VoxelPosition) -> bool {
+    (0..3).all(|axis| position[axis] < size[axis])
+}
+
+/// A grid storing every cell in a flat array, x varying fastest. Fast lookups, memory
+/// proportional to the volume.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DenseGrid {
+    size: VoxelPosition,
+    cells: Vec<Option<u8>>,
+}
+
+impl DenseGrid {
+    /// An empty grid of `size` cells.
+    pub fn new(size: VoxelPosition) -> Self {
+        let volume = size[0] as usize * size[1] as usize * size[2] as usize;
+        DenseGrid { size, cells: vec!(None; volume) }
+    }
+
+    /// Bytes used by the grid, including its cells.
+    pub fn memory_usage(&self) -> usize {
+        mem::size_of::<Self>() + self.cells.len() * mem::size_of::<Option<u8>>()
+    }
+
+    fn index(&self, position: VoxelPosition) -> usize {
+        let (width, depth) = (self.size[0] as usize, self.size[1] as usize);
+        position[0] as usize + width * (position[1] as usize + depth * position[2] as usize)
+    }
+}
+
+impl VoxelGrid for DenseGrid {
+    fn size(&self) -> VoxelPosition {
+        self.size
+    }
+
+    fn get(&self, position: VoxelPosition) -> Option<u8> {
+        if !contains(self.size, position) {
+            return None;
+        }
+        self.cells[self.index(position)]
+    }
+
+    fn set(&mut self, position: VoxelPosition, index: Option<u8>) {
+        assert!(contains(self.size, position), "{:?} lies outside {:?}", position, self.size);
+        let cell = self.index(position);
+        self.cells[cell] = index;
+    }
+
+    fn voxels<'a>(&'a self) -> Box<dyn Iterator<Item = (VoxelPosition, u8)> + 'a> {
+        let (width, depth) = (self.size[0] as usize, self.size[1] as usize);
+        Box::new(self.cells.iter().enumerate().filter_map(move |(cell, index)| {
+            let position = [cell % width, cell / width % depth, cell / width / depth];
+            index.map(|index| {
+                ([position[0] as u32, position[1] as u32, position[2] as u32], index)
+            })
+        }))
+    }
+}
+
+/// Voxels lying outside the size of the model are skipped with a warning.
+impl<'a> From<&'a Model> for DenseGrid {
+    fn from(model: &'a Model) -> Self {
+        let mut grid = DenseGrid::new([model.size.x, model.size.y, model.size.z]);
+        fill_from_model(&mut grid, model);
+        grid
+    }
+}
+
+/// A grid storing only filled cells in a hash map. Memory proportional to the number of voxels,
+/// for large, mostly empty volumes.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SparseGrid {
+    size: VoxelPosition,
+    cells: HashMap<VoxelPosition, u8>,
+}
+
+impl SparseGrid {
+    /// An empty grid of `size` cells.
+    pub fn new(size: VoxelPosition) -> Self {
+        SparseGrid { size, cells: HashMap::new() }
+    }
+}
+
+impl VoxelGrid for SparseGrid {
+    fn size(&self) -> VoxelPosition {
+        self.size
+    }
+
+    fn get(&self, position: VoxelPosition) -> Option<u8> {
+        self.cells.get(&position).cloned()
+    }
+
+    fn set(&mut self, position: VoxelPosition, index: Option<u8>) {
+        assert!(contains(self.size, position), "{:?} lies outside {:?}", position, self.size);
+        match index {
+            Some(index) => self.cells.insert(position, index),
+            None => self.cells.remove(&position),
+        };
+    }
+
+    /// Sorted in z, y, x order like a `DenseGrid`, as the hash map order changes from run to run.
+    fn voxels<'a>(&'a self) -> Box<dyn Iterator<Item = (VoxelPosition, u8)> + 'a> {
+        let mut voxels: Vec<_> = self.cells.iter().map(|(&position, &index)| (position, index))
+            .collect();
+        voxels.sort_by_key(|&(position, _)| (position[2], position[1], position[0]));
+        Box::new(voxels.into_iter())
+    }
+
+    fn len(&self) -> usize {
+        self.cells.len()
+    }
+}
+
+/// Skips voxels outside the model like the `DenseGrid` conversion.
+impl<'a> From<&'a Model> for SparseGrid {
+    fn from(model: &'a Model) -> Self {
+        let mut grid = SparseGrid::new([model.size.x, model.size.y, model.size.z]);
+        fill_from_model(&mut grid, model);
+        grid
+    }
+}
+
+fn fill_from_model<G: VoxelGrid>(grid: &mut G, model: &Model) {
+    let size = grid.size();
+    let mut skipped = 0;
+    for voxel in &model.voxels {
+        let position = [voxel.x as u32, voxel.y as u32, voxel.z as u32];
+        if contains(size, position) {
+            grid.set(position, Some(voxel.i));
+        } else {
+            skipped += 1;
+        }
+    }
+    if skipped > 0 {
+        warn!("Skipped {} voxels lying outside their {:?} model", skipped, size);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn model() -> Model {
+        Model {
+            size: Size { x: 3, y: 2, z: 2 },
+            voxels: vec!(
+                Voxel { x: 0, y: 0, z: 0, i: 1 },
+                Voxel { x: 1, y: 0, z: 0, i: 2 },
+                Voxel { x: 2, y: 1, z: 1, i: 3 },
+            ),
+        }
+    }
+
+    fn behaves_like_the_model<G: VoxelGrid>(mut grid: G) {
+        assert_eq!(grid.size(), [3, 2, 2]);
+        assert_eq!(grid.len(), 3);
+        assert_eq!(grid.get([1, 0, 0]), Some(2));
+        assert_eq!(grid.get([1, 1, 0]), None);
+        assert_eq!(grid.get([9, 0, 0]), None);
+        assert_eq!(grid.neighbours([0, 0, 0]), [None, Some(2), None, None, None, None]);
+        assert_eq!(grid.bounds(), Some(([0, 0, 0], [2, 1, 1])));
+        assert_eq!(to_model(&grid), Some(model()));
+
+        grid.set([0, 0, 0], None);
+        grid.set([1, 1, 0], Some(7));
+        let mut voxels: Vec<_> = grid.voxels().collect();
+        voxels.sort();
+        assert_eq!(voxels, vec!(([1, 0, 0], 2), ([1, 1, 0], 7), ([2, 1, 1], 3)));
+        assert_eq!(grid.bounds(), Some(([1, 0, 0], [2, 1, 1])));
+    }
+
+    #[test]
+    fn dense_grids_hold_the_voxels_of_a_model() {
+        behaves_like_the_model(DenseGrid::from(&model()));
+    }
+
+    #[test]
+    fn sparse_grids_hold_the_voxels_of_a_model() {
+        behaves_like_the_model(SparseGrid::from(&model()));
+    }
+
+    #[test]
+    fn voxels_outside_the_model_are_skipped() {
+        let mut model = model();
+        model.voxels.push(Voxel { x: 3, y: 0, z: 0, i: 4 });
+        behaves_like_the_model(DenseGrid::from(&model));
+        behaves_like_the_model(SparseGrid::from(&model));
+    }
+
+    #[test]
+    fn sparse_grids_list_voxels_like_dense_grids() {
+        let mut model = model();
+        model.voxels.reverse();
+        let dense: Vec<_> = DenseGrid::from(&model).voxels().collect();
+        assert_eq!(SparseGrid::from(&model).voxels().collect::<Vec<_>>(), dense);
+    }
+
+    #[test]
+    #[should_panic]
+    fn setting_a_cell_outside_the_grid_panics() {
+        SparseGrid::new([1, 1, 1]).set([1, 0, 0], Some(0));
+    }
+}
@@ -0,0 +1,335 @@
+use amethyst_assets::AssetStorage;
+use amethyst_core::GlobalTransform;
+use amethyst_core::cgmath::{Matrix4, SquareMatrix};
+use amethyst_renderer::{ActiveCamera, Attributes, Camera, Color, ColorMask, Effect, Encoder,
+                         Factory, Mesh, MeshHandle, NewEffect, Position, Query, Resources,
+                         Visibility, ALPHA};
+use amethyst_renderer::error::Result;
+use amethyst_renderer::pipe::DepthMode;
+use amethyst_renderer::pipe::pass::{Pass, PassData};
+use gfx_core::Factory as GfxFactory;
+use gfx_core::buffer::Role;
+use gfx_core::format::{ChannelType, Format, SurfaceType};
+use gfx_core::handle::Buffer;
+use gfx_core::memory::{Bind, Pod, Typed, Usage};
+use gfx_core::pso::Element;
+use fog::{FogArgs, VoxelFog};
+use glsl_layout::*;
+use renderer::{get_camera, is_translucent, set_attribute_buffers, set_fragment_args,
+               warn_missing_transforms, FragmentArgs, VoxelTint, Warnings};
+
+use specs::{Entities, Join, Read, ReadStorage};
+
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::mem;
+
+static VERT_SRC: &[u8] = include_bytes!("shaders/vertex/instanced.glsl");
+static FRAG_SRC: &[u8] = include_bytes!("shaders/fragment/flat.glsl");
+
+const VEC4: Format = Format(SurfaceType::R32_G32_B32_A32, ChannelType::Float);
+
+/// Per-instance vertex attributes, the columns of the model matrix followed by the tint.
+static INSTANCE_ATTRIBUTES: [(&str, Element<Format>); 5] = [
+    ("model_col0", Element { format: VEC4, offset: 0 }),
+    ("model_col1", Element { format: VEC4, offset: 16 }),
+    ("model_col2", Element { format: VEC4, offset: 32 }),
+    ("model_col3", Element { format: VEC4, offset: 48 }),
+    ("tint", Element { format: VEC4, offset: 64 }),
+];
+
+#[repr(C, align(16))]
+#[derive(Clone, Copy, Debug, Uniform)]
+pub(crate) struct ViewArgs {
+    proj: mat4,
+    view: mat4,
+}
+
+/// Data uploaded to the per-instance vertex buffer for every entity.
+#[repr(C)]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) struct Instance {
+    model: [[f32; 4]; 4],
+    tint: [f32; 4],
+}
+
+unsafe impl Pod for Instance {}
+
+impl Instance {
+    fn size() -> usize {
+        mem::size_of::<Instance>()
+    }
+}
+
+/// Draw mesh without lighting, batching entities that share a `MeshHandle` into a single
+/// instanced draw call. Honours `VoxelTint` per instance and `VoxelFog`. Translucent instances
+/// are batched separately and drawn after the opaque ones.
+/// `V` is `VertexFormat`
+#[derive(Derivative, Clone, Debug, PartialEq)]
+#[derivative(Default(bound = "V: Query<(Position, Color)>, Self: Pass"))]
+pub struct DrawVoxelsInstanced<V> {
+    _pd: PhantomData<V>,
+    warnings: Warnings,
+    /// Per-instance data, refilled for every group and only reallocated when a group outgrows it.
+    instances: Option<Buffer<Resources, Instance>>,
+}
+
+impl<V> DrawVoxelsInstanced<V>
+    where
+        V: Query<(Position, Color)>,
+        Self: Pass,
+{
+    /// Create instance of `DrawVoxelsInstanced` pass
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl<'a, V> PassData<'a> for DrawVoxelsInstanced<V>
+    where
+        V: Query<(Position, Color)>,
+{
+    type Data = (
+        Entities<'a>,
+        Option<Read<'a, ActiveCamera>>,
+        ReadStorage<'a, Camera>,
+        Read<'a, AssetStorage<Mesh>>,
+        Option<Read<'a, Visibility>>,
+        ReadStorage<'a, MeshHandle>,
+        ReadStorage<'a, GlobalTransform>,
+        ReadStorage<'a, VoxelTint>,
+        Option<Read<'a, VoxelFog>>,
+    );
+}
+
+impl<V> Pass for DrawVoxelsInstanced<V>
+    where
+        V: Query<(Position, Color)>,
+{
+    fn compile(&mut self, effect: NewEffect) -> Result<Effect> {
+        let mut builder = effect.simple(VERT_SRC, FRAG_SRC);
+        builder
+            .with_raw_constant_buffer(
+                "ViewArgs",
+                mem::size_of::<<ViewArgs as Uniform>::Std140>(),
+                1,
+            )
+            .with_raw_constant_buffer(
+                "FragmentArgs",
+                mem::size_of::<<FragmentArgs as Uniform>::Std140>(),
+                1,
+            )
+            .with_raw_vertex_buffer(V::QUERIED_ATTRIBUTES, V::size() as u8, 0)
+            .with_raw_vertex_buffer(&INSTANCE_ATTRIBUTES, Instance::size() as u8, 1);
+        builder.with_blended_output(
+            "color",
+            ColorMask::all(),
+            ALPHA,
+            Some(DepthMode::LessEqualWrite),
+        );
+        builder.build()
+    }
+
+    fn apply<'a, 'b: 'a>(
+        &'a mut self,
+        encoder: &mut Encoder,
+        effect: &mut Effect,
+        mut factory: Factory,
+        (
+            entities,
+            active,
+            camera,
+            mesh_storage,
+            visibility,
+            mesh,
+            global,
+            tint,
+            fog,
+        ): <Self as PassData<'a>>::Data,
+    ) {
+        let camera = get_camera(active, &camera, &global);
+        warn_missing_transforms(&mut self.warnings, &entities, &mesh, &global,
+                                "DrawVoxelsInstanced");
+        let instance = |mesh: &MeshHandle, global: &GlobalTransform, tint: Option<&VoxelTint>| {
+            let instance = Instance {
+                model: global.0.into(),
+                tint: tint.cloned().unwrap_or_default().0,
+            };
+            ((mesh.clone(), is_translucent(tint)), instance)
+        };
+
+        let mut groups = match visibility {
+            None => group_instances(
+                (&mesh, &global, tint.maybe()).join()
+                    .map(|(mesh, global, tint)| instance(mesh, global, tint))
+            ),
+            Some(ref visibility) => group_instances(
+                (&mesh, &global, tint.maybe(), &visibility.visible_unordered).join()
+                    .map(|(mesh, global, tint, _)| instance(mesh, global, tint))
+                    .chain(visibility.visible_ordered.iter().filter_map(|entity| {
+                        mesh.get(*entity)
+                            .and_then(|mesh| global.get(*entity).map(|global| (mesh, global)))
+                            .map(|(mesh, global)| instance(mesh, global, tint.get(*entity)))
+                    }))
+            ),
+        };
+        // Opaque groups go first, so that translucent ones blend over everything behind them.
+        groups.sort_by_key(|&((_, translucent), _)| translucent);
+
+        let largest = groups.iter().map(|&(_, ref instances)| instances.len()).max().unwrap_or(0);
+        if !reserve_instances(&mut self.instances, &mut factory, largest) {
+            return;
+        }
+        let buffer = self.instances.as_ref().unwrap();
+
+        set_view_args(effect, encoder, camera);
+        let fog = FogArgs::new(fog.as_ref().map(|fog| &**fog), camera);
+        set_fragment_args(effect, encoder, None, &fog);
+        for ((handle, _), instances) in groups {
+            if let Some(mesh) = mesh_storage.get(&handle) {
+                draw_instances(
+                    encoder,
+                    effect,
+                    buffer,
+                    &mut self.warnings,
+                    mesh,
+                    &instances,
+                    &[V::QUERIED_ATTRIBUTES],
+                );
+            }
+        }
+    }
+}
+
+/// Groups instances by mesh. Groups are ordered by the first appearance of their mesh, and
+/// instances keep their relative order within a group.
+pub(crate) fn group_instances<K, T, I>(instances: I) -> Vec<(K, Vec<T>)>
+    where
+        K: Clone + Eq + Hash,
+        I: IntoIterator<Item = (K, T)>,
+{
+    let mut groups: Vec<(K, Vec<T>)> = Vec::new();
+    let mut lookup = HashMap::new();
+    for (key, instance) in instances {
+        let index = *lookup.entry(key.clone()).or_insert_with(|| {
+            groups.push((key, Vec::new()));
+            groups.len() - 1
+        });
+        groups[index].1.push(instance);
+    }
+    groups
+}
+
+/// Number of instances the buffer is allocated for when `count` don't fit, leaving room to grow.
+pub(crate) fn instance_capacity(count: usize) -> usize {
+    count.max(1).next_power_of_two()
+}
+
+/// Makes sure `buffer` holds at least `count` instances, replacing it with a larger one if not.
+fn reserve_instances(
+    buffer: &mut Option<Buffer<Resources, Instance>>,
+    factory: &mut Factory,
+    count: usize,
+) -> bool {
+    if buffer.as_ref().map_or(false, |buffer| buffer.len() >= count) {
+        return true;
+    }
+    let capacity = instance_capacity(count);
+    match factory.create_buffer(capacity, Role::Vertex, Usage::Dynamic, Bind::empty()) {
+        Ok(larger) => {
+            *buffer = Some(larger);
+            true
+        }
+        Err(e) => {
+            error!("Unable to create instance buffer for {} instances: {:?}", capacity, e);
+            false
+        }
+    }
+}
+
+fn draw_instances(
+    encoder: &mut Encoder,
+    effect: &mut Effect,
+    buffer: &Buffer<Resources, Instance>,
+    warnings: &mut Warnings,
+    mesh: &Mesh,
+    instances: &[Instance],
+    attributes: &[Attributes<'static>],
+) {
+    if !set_attribute_buffers(effect, warnings, mesh, attributes) {
+        effect.clear();
+        return;
+    }
+
+    if let Err(e) = encoder.update_buffer(buffer, instances, 0) {
+        error!("Unable to upload instances: {:?}", e);
+        effect.clear();
+        return;
+    }
+    effect.data.vertex_bufs.push(buffer.raw().clone());
+
+    let mut slice = mesh.slice().clone();
+    slice.instances = Some((instances.len() as u32, 0));
+    effect.draw(&slice, encoder);
+    effect.clear();
+}
+
+fn set_view_args(
+    effect: &mut Effect,
+    encoder: &mut Encoder,
+    camera: Option<(&Camera, &GlobalTransform)>,
+) {
+    let view_args = camera
+        .as_ref()
+        .map(|&(ref cam, ref transform)| ViewArgs {
+            proj: cam.proj.into(),
+            view: transform.0.invert().unwrap().into(),
+        })
+        .unwrap_or_else(|| ViewArgs {
+            proj: Matrix4::one().into(),
+            view: Matrix4::one().into(),
+        });
+    effect.update_constant_buffer("ViewArgs", &view_args.std140(), encoder);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instances_are_grouped_by_mesh_in_first_seen_order() {
+        let groups = group_instances(vec!((7, 'a'), (3, 'b'), (7, 'c'), (5, 'd'), (3, 'e')));
+        assert_eq!(groups, vec!((7, vec!('a', 'c')), (3, vec!('b', 'e')), (5, vec!('d'))));
+    }
+
+    #[test]
+    fn entities_sharing_a_mesh_are_drawn_together() {
+        let at = |x: f32| Instance { model: [[x; 4]; 4], tint: [1.0; 4] };
+        let groups = group_instances(vec!(
+            (("tree", false), at(0.0)),
+            (("rock", false), at(1.0)),
+            (("tree", false), at(2.0)),
+            (("tree", true), at(3.0)),
+            (("tree", false), at(4.0)),
+        ));
+        assert_eq!(groups, vec!(
+            (("tree", false), vec!(at(0.0), at(2.0), at(4.0))),
+            (("rock", false), vec!(at(1.0))),
+            (("tree", true), vec!(at(3.0))),
+        ));
+    }
+
+    #[test]
+    fn the_instance_buffer_grows_in_powers_of_two() {
+        assert_eq!(instance_capacity(0), 1);
+        assert_eq!(instance_capacity(5), 8);
+        assert_eq!(instance_capacity(64), 64);
+    }
+
+    #[test]
+    fn instance_size_matches_the_declared_attributes() {
+        let (_, last) = INSTANCE_ATTRIBUTES[INSTANCE_ATTRIBUTES.len() - 1];
+        assert_eq!(Instance::size(), last.offset as usize + 16);
+    }
+}
@@ -1,22 +1,115 @@
+#[cfg(feature = "amethyst")]
 extern crate amethyst_assets;
+#[cfg(feature = "amethyst")]
 extern crate amethyst_core;
+#[cfg(feature = "amethyst")]
 extern crate amethyst_renderer;
 #[cfg(test)]
 extern crate avow;
+#[cfg(feature = "amethyst")]
 #[macro_use]
 extern crate derivative;
 extern crate dot_vox;
+#[cfg(feature = "amethyst")]
 #[macro_use]
 extern crate glsl_layout;
 #[macro_use]
 extern crate lazy_static;
 #[macro_use]
 extern crate log;
+#[cfg(feature = "amethyst")]
 extern crate gfx_core;
+#[cfg(feature = "parallel")]
+extern crate rayon;
+#[cfg(feature = "serde")]
+#[macro_use]
+extern crate serde;
+#[cfg(all(test, feature = "serde"))]
+extern crate serde_json;
+#[cfg(feature = "amethyst")]
 extern crate specs;
 
+#[cfg(feature = "amethyst")]
+mod debug;
+#[cfg(feature = "amethyst")]
 mod dot_vox_format;
+mod error;
+#[cfg(feature = "amethyst")]
+mod fog;
+mod generate;
+mod grid;
+#[cfg(feature = "amethyst")]
+mod instancing;
+mod limits;
+#[cfg(feature = "amethyst")]
+mod lod;
+#[cfg(feature = "amethyst")]
+mod markers;
+#[cfg(feature = "amethyst")]
+mod mesh_cache;
+mod meshing;
+mod octree;
+#[cfg(feature = "amethyst")]
 mod renderer;
+mod rle;
+#[cfg(feature = "amethyst")]
+mod scene;
+#[cfg(feature = "amethyst")]
+mod selection;
+#[cfg(feature = "amethyst")]
+mod shading;
+#[cfg(feature = "amethyst")]
+mod spawn;
+#[cfg(feature = "amethyst")]
+mod stats;
+#[cfg(feature = "amethyst")]
+mod surface_nets;
+#[cfg(feature = "amethyst")]
+mod texture;
 
-pub use renderer::DrawVoxels;
-pub use dot_vox_format::DotVoxFormat;
\ No newline at end of file
+#[cfg(feature = "amethyst")]
+pub use renderer::{DrawVoxels, VoxelTint};
+#[cfg(feature = "amethyst")]
+pub use debug::{cell_lines, normal_lines, wireframe_lines, DebugMeshKind, DebugMeshOptions,
+                DebugModes, DotVoxDebugFormat, DrawVoxelDebug, VoxelDebug, VoxelDebugSettings};
+#[cfg(feature = "amethyst")]
+pub use dot_vox_format::{import_with_stats, mesh_grid, mesh_model, validate, DotVoxFormat,
+                         DotVoxOptions};
+#[cfg(feature = "parallel")]
+pub use dot_vox_format::mesh_model_parallel;
+pub use error::VoxError;
+#[cfg(feature = "amethyst")]
+pub use fog::{FogFalloff, VoxelFog};
+pub use generate::{fill_box, fill_cylinder, fill_sphere, fill_terrain, Heightmap, HeightmapError};
+pub use grid::{offset_position, to_model, DenseGrid, SparseGrid, VoxelGrid, VoxelPosition,
+               NEIGHBOUR_OFFSETS};
+#[cfg(feature = "amethyst")]
+pub use instancing::DrawVoxelsInstanced;
+pub use limits::{Budget, ImportLimits, Strictness};
+#[cfg(feature = "amethyst")]
+pub use lod::{downsample, Lod, LodLevel, LodVoting, VoxelLod, VoxelLodSystem};
+#[cfg(feature = "amethyst")]
+pub use markers::{find_markers, DotVoxMarkersFormat, VoxMarkers};
+pub use meshing::{mesh_buffers, palette_to_rgba, MeshBuffers};
+pub use octree::OctreeGrid;
+pub use rle::{ChunkError, RleChunk, MAX_CHUNK_VOLUME, RLE_CHUNK_VERSION};
+#[cfg(feature = "amethyst")]
+pub use scene::{Layer, LayerFilter, Scene, SceneModel};
+#[cfg(feature = "amethyst")]
+pub use selection::{outline_hull, DotVoxOutlineFormat, DrawVoxelHighlight, DrawVoxelOutline,
+                    OutlineOptions, VoxelHighlight, VoxelSelected};
+#[cfg(feature = "amethyst")]
+pub use shading::{shade_faces, FaceShading};
+#[cfg(feature = "amethyst")]
+pub use spawn::{spawn_scene, Named, VoxNames};
+#[cfg(feature = "amethyst")]
+pub use stats::ImportStats;
+#[cfg(feature = "amethyst")]
+pub use surface_nets::{surface_nets, Extractor};
+#[cfg(feature = "amethyst")]
+pub use texture::{mesh_model_textured, palette_tex_coord, palette_texels, DotVoxPaletteFormat,
+                  DotVoxTexturedFormat, TexturedOptions, TexturedVertices,
+                  PALETTE_TEXTURE_WIDTH};
+#[cfg(feature = "amethyst")]
+pub use mesh_cache::{fnv_hash, MeshCache, MeshCacheFormat, SubMesh, MESH_CACHE_EXTENSION,
+                     MESH_CACHE_VERSION};
\ No newline at end of file
@@ -0,0 +1,114 @@
+use dot_vox::Model;
+use error::VoxError;
+
+/// A quantity `ImportLimits` caps.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Budget {
+    Voxels,
+    Vertices,
+    Models,
+}
+
+/// What an import going over its `ImportLimits` does.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Strictness {
+    /// The import fails with `VoxError::OverBudget`.
+    Error,
+    /// A warning is logged and the model is meshed anyway.
+    Warn,
+}
+
+impl Default for Strictness {
+    fn default() -> Self {
+        Strictness::Error
+    }
+}
+
+/// Caps on the size of an import, so that e.g. a solid 256³ block committed by mistake fails fast
+/// instead of producing a multi-gigabyte mesh. Models and voxels are checked straight after
+/// parsing, before any processing. Unset limits are not checked.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ImportLimits {
+    pub max_voxels: Option<usize>,
+    /// Vertices of the mesh, counted before meshing for cubes and once finished for surfaces.
+    pub max_vertices: Option<usize>,
+    pub max_models: Option<usize>,
+    pub strictness: Strictness,
+}
+
+impl ImportLimits {
+    /// Checks the models of a file against `max_models` and `max_voxels`. Imports call it for
+    /// you, like `check_vertices`; both are public for meshing done outside the asset formats.
+    pub fn check_models(&self, models: &[&Model]) -> Result<(), VoxError> {
+        let voxels: usize = models.iter().map(|model| model.voxels.len()).sum();
+        self.enforce(Budget::Models, models.len(), self.max_models)?;
+        self.enforce(Budget::Voxels, voxels, self.max_voxels)
+    }
+
+    /// Checks the number of vertices of a finished mesh against `max_vertices`.
+    pub fn check_vertices(&self, vertices: usize) -> Result<(), VoxError> {
+        self.enforce(Budget::Vertices, vertices, self.max_vertices)
+    }
+
+    fn enforce(&self, budget: Budget, count: usize, limit: Option<usize>) -> Result<(), VoxError> {
+        let limit = match limit {
+            Some(limit) if count > limit => limit,
+            _ => return Ok(()),
+        };
+        let error = VoxError::OverBudget { budget, count, limit };
+        match self.strictness {
+            Strictness::Error => Err(error),
+            Strictness::Warn => {
+                warn!("{}", error);
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dot_vox::{Size, Voxel};
+
+    fn row(length: u8) -> Model {
+        Model {
+            size: Size { x: length as u32, y: 1, z: 1 },
+            voxels: (0..length).map(|x| Voxel { x, y: 0, z: 0, i: 0 }).collect(),
+        }
+    }
+
+    #[test]
+    fn the_first_limit_exceeded_fails_the_import() {
+        let (small, large) = (row(2), row(10));
+        let limits = ImportLimits {
+            max_voxels: Some(10),
+            max_vertices: Some(36 * 11),
+            max_models: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(limits.check_models(&[&large]), Ok(()));
+        assert_eq!(limits.check_models(&[&small, &small]),
+                   Err(VoxError::OverBudget { budget: Budget::Models, count: 2, limit: 1 }));
+        assert_eq!(limits.check_models(&[&row(11)]),
+                   Err(VoxError::OverBudget { budget: Budget::Voxels, count: 11, limit: 10 }));
+
+        assert_eq!(limits.check_vertices(36 * 11), Ok(()));
+        assert_eq!(
+            limits.check_vertices(36 * 12),
+            Err(VoxError::OverBudget { budget: Budget::Vertices, count: 432, limit: 396 })
+        );
+    }
+
+    #[test]
+    fn lenient_limits_only_warn() {
+        let limits = ImportLimits {
+            max_voxels: Some(1),
+            max_models: Some(0),
+            strictness: Strictness::Warn,
+            ..Default::default()
+        };
+        assert_eq!(limits.check_models(&[&row(10)]), Ok(()));
+        assert_eq!(ImportLimits { max_vertices: Some(0), ..limits }.check_vertices(36), Ok(()));
+    }
+}
@@ -0,0 +1,234 @@
+use amethyst_core::GlobalTransform;
+use amethyst_core::cgmath::InnerSpace;
+use amethyst_renderer::{ActiveCamera, Camera, MeshHandle, PosColor};
+use dot_vox::{Model, Size, Voxel};
+use renderer::get_camera;
+use specs::{Component, DenseVecStorage, Join, Read, ReadStorage, System, WriteStorage};
+
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+/// How a downsampled cell decides whether it is solid. Solid cells always take the most common
+/// palette index of the voxels they cover.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LodVoting {
+    /// Empty space takes part in the vote: a cell is only solid when more than half of the
+    /// voxels it covers are. Keeps silhouettes tight but can erase thin details.
+    Majority,
+    /// A cell is solid when any voxel it covers is, so thin details survive at the cost of
+    /// slightly bloated silhouettes.
+    AnySolid,
+}
+
+/// Level of detail to generate when importing a `.vox` file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Lod {
+    /// Number of voxels along each axis merged into one, usually 2, 4 or 8.
+    pub factor: u8,
+    pub voting: LodVoting,
+}
+
+/// Downsamples a model by `factor` along each axis. Solid cells take the most common palette
+/// index of the voxels they cover, ties going to the lowest index.
+pub fn downsample(model: &Model, factor: u8, voting: LodVoting) -> Model {
+    let factor = factor.max(1) as u32;
+    let cells_along = |size: u32| (size + factor - 1) / factor;
+    let size = Size {
+        x: cells_along(model.size.x),
+        y: cells_along(model.size.y),
+        z: cells_along(model.size.z),
+    };
+
+    let mut cells: HashMap<(u32, u32, u32), HashMap<u8, u32>> = HashMap::new();
+    for voxel in &model.voxels {
+        let cell = (voxel.x as u32 / factor, voxel.y as u32 / factor, voxel.z as u32 / factor);
+        *cells.entry(cell).or_insert_with(HashMap::new).entry(voxel.i).or_insert(0) += 1;
+    }
+
+    let covered = |cell: u32, size: u32| size.saturating_sub(cell * factor).min(factor).max(1);
+    let mut voxels: Vec<Voxel> = cells.into_iter()
+        .filter_map(|((x, y, z), counts)| {
+            let solid: u32 = counts.values().sum();
+            let volume = covered(x, model.size.x) * covered(y, model.size.y) *
+                covered(z, model.size.z);
+            if voting == LodVoting::Majority && solid * 2 <= volume.max(solid) {
+                return None;
+            }
+            let (i, _) = counts.into_iter()
+                .max_by(|a, b| a.1.cmp(&b.1).then(b.0.cmp(&a.0)))
+                .unwrap();
+            Some(Voxel { x: x as u8, y: y as u8, z: z as u8, i })
+        })
+        .collect();
+    voxels.sort_by_key(|v| (v.z, v.y, v.x));
+    Model { size, voxels }
+}
+
+/// Moves vertices meshed from a downsampled model back into the space of the source model, so
+/// every level of detail lines up with the full detail mesh.
+pub(crate) fn scale_to_source(vertices: &mut [PosColor], factor: u8) {
+    for vertex in vertices {
+        scale_position_to_source(&mut vertex.position, factor);
+    }
+}
+
+pub(crate) fn scale_position_to_source(position: &mut [f32; 3], factor: u8) {
+    let factor = factor.max(1) as f32;
+    let offset = (factor - 1.0) / 2.0;
+    for coordinate in position.iter_mut() {
+        *coordinate = *coordinate * factor + offset;
+    }
+}
+
+/// A mesh to use once the camera is at least `min_distance` away.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LodLevel {
+    pub min_distance: f32,
+    pub mesh: MeshHandle,
+}
+
+/// Levels of detail for an entity. `VoxelLodSystem` keeps the entity's `MeshHandle` set to the
+/// level matching its distance from the camera.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VoxelLod {
+    levels: Vec<LodLevel>,
+}
+
+impl VoxelLod {
+    /// Levels with a NaN `min_distance` are never selected.
+    pub fn new(mut levels: Vec<LodLevel>) -> Self {
+        levels.sort_by(|a, b| by_distance(a.min_distance, b.min_distance));
+        VoxelLod { levels }
+    }
+
+    pub fn levels(&self) -> &[LodLevel] {
+        &self.levels
+    }
+
+    /// Mesh to show at `distance`, or `None` when the camera is closer than every level.
+    pub fn select(&self, distance: f32) -> Option<&MeshHandle> {
+        self.levels.iter()
+            .rev()
+            .find(|level| level.min_distance <= distance)
+            .map(|level| &level.mesh)
+    }
+}
+
+/// Orders distances from near to far, with NaN after every number.
+fn by_distance(a: f32, b: f32) -> Ordering {
+    a.partial_cmp(&b).unwrap_or_else(|| a.is_nan().cmp(&b.is_nan()))
+}
+
+impl Component for VoxelLod {
+    type Storage = DenseVecStorage<Self>;
+}
+
+/// Swaps the `MeshHandle` of entities with a `VoxelLod` based on their distance from the active
+/// camera. Add it after the transform system, so distances use this frame's transforms.
+#[derive(Default)]
+pub struct VoxelLodSystem;
+
+impl<'a> System<'a> for VoxelLodSystem {
+    type SystemData = (
+        Option<Read<'a, ActiveCamera>>,
+        ReadStorage<'a, Camera>,
+        ReadStorage<'a, GlobalTransform>,
+        ReadStorage<'a, VoxelLod>,
+        WriteStorage<'a, MeshHandle>,
+    );
+
+    fn run(&mut self, (active, camera, global, lod, mut mesh): Self::SystemData) {
+        let eye = match get_camera(active, &camera, &global) {
+            Some((_, transform)) => transform.0.w.truncate(),
+            None => return,
+        };
+        for (lod, global, mesh) in (&lod, &global, &mut mesh).join() {
+            let distance = (global.0.w.truncate() - eye).magnitude();
+            if let Some(selected) = lod.select(distance) {
+                if *mesh != *selected {
+                    *mesh = selected.clone();
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn voxel(x: u8, y: u8, z: u8, i: u8) -> Voxel {
+        Voxel { x, y, z, i }
+    }
+
+    fn model(size: u32, voxels: Vec<Voxel>) -> Model {
+        Model { size: Size { x: size, y: size, z: size }, voxels }
+    }
+
+    fn block(count: usize, indices: &[u8]) -> Vec<Voxel> {
+        (0..count)
+            .map(|n| {
+                voxel(n as u8 & 1, n as u8 >> 1 & 1, n as u8 >> 2 & 1, indices[n % indices.len()])
+            })
+            .collect()
+    }
+
+    #[test]
+    fn majority_needs_more_than_half_of_a_cell() {
+        let five = downsample(&model(2, block(5, &[3])), 2, LodVoting::Majority);
+        assert_eq!(five.size, Size { x: 1, y: 1, z: 1 });
+        assert_eq!(five.voxels, vec!(voxel(0, 0, 0, 3)));
+
+        let four = downsample(&model(2, block(4, &[3])), 2, LodVoting::Majority);
+        assert_eq!(four.voxels, vec!());
+    }
+
+    #[test]
+    fn any_solid_keeps_every_occupied_cell() {
+        let result = downsample(&model(4, vec!(voxel(3, 3, 3, 9))), 2, LodVoting::AnySolid);
+        assert_eq!(result.size, Size { x: 2, y: 2, z: 2 });
+        assert_eq!(result.voxels, vec!(voxel(1, 1, 1, 9)));
+    }
+
+    #[test]
+    fn cells_take_the_most_common_index_with_ties_going_low() {
+        let mostly_seven = downsample(&model(2, block(8, &[7, 7, 2])), 2, LodVoting::Majority);
+        assert_eq!(mostly_seven.voxels, vec!(voxel(0, 0, 0, 7)));
+
+        let tied = downsample(&model(2, block(8, &[7, 2])), 2, LodVoting::Majority);
+        assert_eq!(tied.voxels, vec!(voxel(0, 0, 0, 2)));
+    }
+
+    #[test]
+    fn cells_clipped_by_the_model_bounds_vote_on_what_they_cover() {
+        let model = Model {
+            size: Size { x: 3, y: 1, z: 1 },
+            voxels: vec!(voxel(0, 0, 0, 1), voxel(2, 0, 0, 4)),
+        };
+        let result = downsample(&model, 2, LodVoting::Majority);
+        assert_eq!(result.size, Size { x: 2, y: 1, z: 1 });
+        assert_eq!(result.voxels, vec!(voxel(1, 0, 0, 4)));
+    }
+
+    #[test]
+    fn output_is_ordered_by_position() {
+        let model = model(8, vec!(voxel(7, 7, 7, 1), voxel(0, 0, 5, 2), voxel(6, 0, 0, 3)));
+        let result = downsample(&model, 4, LodVoting::AnySolid);
+        assert_eq!(result.voxels, vec!(voxel(1, 0, 0, 3), voxel(0, 0, 1, 2), voxel(1, 1, 1, 1)));
+    }
+
+    #[test]
+    fn levels_sort_by_distance_with_nan_last() {
+        let mut distances = vec!(20.0, ::std::f32::NAN, 0.0, 5.0);
+        distances.sort_by(|&a, &b| by_distance(a, b));
+        assert_eq!(distances[..3], [0.0, 5.0, 20.0]);
+        assert!(distances[3].is_nan());
+    }
+
+    #[test]
+    fn downsampled_vertices_line_up_with_the_source_model() {
+        let mut vertices = vec!(PosColor { position: [-0.5, 0.5, 1.5], color: [1.0; 4] });
+        scale_to_source(&mut vertices, 4);
+        assert_eq!(vertices[0].position, [-0.5, 3.5, 7.5]);
+    }
+}
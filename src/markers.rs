@@ -0,0 +1,115 @@
+use amethyst_assets::{Asset, Handle, ProcessingState, Result, SimpleFormat};
+use dot_vox::Model;
+use dot_vox_format::{self, DotVoxOptions};
+use specs::VecStorage;
+
+use std::collections::HashMap;
+
+/// Where the marker voxels of a `.vox` file are, e.g. spawn points or pickups painted with
+/// reserved palette indices.
+///
+/// Positions are voxel centres in the space of the mesh imported with the same options, so a
+/// marker can be placed with the `Transform` of the entity holding that mesh. Load it with
+/// `DotVoxMarkersFormat` and add a `Processor::<VoxMarkers>` to the dispatcher.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VoxMarkers {
+    positions: HashMap<u8, Vec<[f32; 3]>>,
+}
+
+impl VoxMarkers {
+    /// Centres of the voxels painted with the palette index `index`, in file order.
+    pub fn positions(&self, index: u8) -> &[[f32; 3]] {
+        self.positions.get(&index).map(|positions| &positions[..]).unwrap_or(&[])
+    }
+}
+
+impl Asset for VoxMarkers {
+    const NAME: &'static str = "dot_vox_amethyst::VoxMarkers";
+    type Data = Self;
+    type HandleStorage = VecStorage<Handle<Self>>;
+}
+
+impl Into<Result<ProcessingState<VoxMarkers>>> for VoxMarkers {
+    fn into(self) -> Result<ProcessingState<VoxMarkers>> {
+        Ok(ProcessingState::Loaded(self))
+    }
+}
+
+/// Imports the markers named by `DotVoxOptions::markers` from the models the options select.
+#[derive(Clone)]
+pub struct DotVoxMarkersFormat;
+
+impl SimpleFormat<VoxMarkers> for DotVoxMarkersFormat {
+    const NAME: &'static str = "MAGICAVOXEL_DOT_VOX_MARKERS";
+    type Options = DotVoxOptions;
+
+    fn import(&self, bytes: Vec<u8>, options: DotVoxOptions) -> Result<VoxMarkers> {
+        let (models, _) = dot_vox_format::selected_models(&bytes, &options)?;
+        let mut positions: HashMap<u8, Vec<[f32; 3]>> = HashMap::new();
+        for (_, model, origin) in models {
+            for (index, found) in find_markers(&model, &options.markers) {
+                let placed = found.into_iter().map(|position| [
+                    position[0] + origin[0],
+                    position[1] + origin[1],
+                    position[2] + origin[2],
+                ]);
+                positions.entry(index).or_insert_with(Vec::new).extend(placed);
+            }
+        }
+        Ok(VoxMarkers { positions })
+    }
+}
+
+/// Centres of the voxels of `model` painted with one of `indices`, grouped by index.
+pub fn find_markers(model: &Model, indices: &[u8]) -> HashMap<u8, Vec<[f32; 3]>> {
+    let mut markers: HashMap<u8, Vec<[f32; 3]>> = HashMap::new();
+    for voxel in model.voxels.iter().filter(|voxel| indices.contains(&voxel.i)) {
+        markers.entry(voxel.i).or_insert_with(Vec::new)
+            .push([voxel.x as f32, voxel.y as f32, voxel.z as f32]);
+    }
+    markers
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dot_vox::{Size, Voxel};
+    use dot_vox_format::mesh;
+    use scene::tests::SceneBuilder;
+    use scene::LayerFilter;
+
+    #[test]
+    fn markers_are_grouped_by_palette_index() {
+        let model = Model {
+            size: Size { x: 3, y: 1, z: 1 },
+            voxels: vec!(
+                Voxel { x: 0, y: 0, z: 0, i: 0 },
+                Voxel { x: 1, y: 0, z: 0, i: 7 },
+                Voxel { x: 2, y: 0, z: 0, i: 9 },
+            ),
+        };
+        let markers = find_markers(&model, &[7, 9, 12]);
+        assert_eq!(markers[&7], vec!([1.0, 0.0, 0.0]));
+        assert_eq!(markers[&9], vec!([2.0, 0.0, 0.0]));
+        assert!(!markers.contains_key(&12));
+    }
+
+    #[test]
+    fn marker_voxels_are_left_out_of_the_mesh_and_reported_where_the_scene_places_them() {
+        let bytes = SceneBuilder::new()
+            .layer("", false)
+            .model(0, 0, [0, 0, 0], false)
+            .model(7, 0, [10, 0, 0], false)
+            .build();
+        let options = DotVoxOptions {
+            layers: Some(LayerFilter::All),
+            markers: vec!(7),
+            ..DotVoxOptions::default()
+        };
+        assert_eq!(mesh(&bytes, &options).unwrap().len(), 36);
+
+        let markers = DotVoxMarkersFormat.import(bytes, options).unwrap();
+        assert_eq!(markers.positions(7), &[[10.0, 0.0, 0.0]]);
+        assert!(markers.positions(9).is_empty());
+    }
+}
@@ -0,0 +1,332 @@
+use amethyst_assets::{Result, SimpleFormat};
+use amethyst_renderer::{Mesh, MeshData, PosColor};
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+const MAGIC: &[u8; 4] = b"DVXM";
+
+/// Version of the binary layout written by `MeshCache::encode`. Bump whenever the layout or the
+/// meshing output changes, so that stale cache files are regenerated. Version 2 covers shading,
+/// surface nets, scenes and markers; version 3 records a sub-mesh per model.
+pub const MESH_CACHE_VERSION: u32 = 3;
+
+/// File extension used for cache entries.
+pub const MESH_CACHE_EXTENSION: &str = "dvxm";
+
+/// Range of `MeshCache::indices` belonging to a single model of the source file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SubMesh {
+    pub model: u32,
+    pub start: u32,
+    pub count: u32,
+}
+
+/// Already-meshed voxel data, stored as an indexed vertex list so it can be written to and read
+/// from disk without touching the original `.vox` file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MeshCache {
+    /// Key of the import the mesh was built by, as computed from the file and its options.
+    pub key: u64,
+    pub bounds: ([f32; 3], [f32; 3]),
+    pub vertices: Vec<PosColor>,
+    pub indices: Vec<u32>,
+    pub sub_meshes: Vec<SubMesh>,
+}
+
+impl MeshCache {
+    /// Builds a cache entry from a triangle list, merging identical vertices. `models` lists the
+    /// file index and vertex count of each model meshed into `triangles`, in order.
+    pub fn from_triangles(key: u64, models: &[(u32, usize)], triangles: &[PosColor]) -> MeshCache {
+        let mut lookup = HashMap::new();
+        let mut vertices = Vec::new();
+        let indices: Vec<u32> = triangles.iter()
+            .map(|vertex| {
+                *lookup.entry(vertex_key(vertex)).or_insert_with(|| {
+                    vertices.push(*vertex);
+                    vertices.len() as u32 - 1
+                })
+            })
+            .collect();
+        let mut start = 0;
+        let sub_meshes = models.iter()
+            .map(|&(model, count)| {
+                let sub_mesh = SubMesh { model, start, count: count as u32 };
+                start += count as u32;
+                sub_mesh
+            })
+            .collect();
+        debug_assert_eq!(start as usize, indices.len());
+        MeshCache {
+            key,
+            bounds: bounds(&vertices),
+            vertices,
+            indices,
+            sub_meshes,
+        }
+    }
+
+    /// Expands the indexed data back into the triangle list expected by `MeshData::PosColor`.
+    pub fn to_triangles(&self) -> Vec<PosColor> {
+        self.indices.iter()
+            .map(|&index| self.vertices[index as usize])
+            .collect()
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(
+            48 + self.vertices.len() * 28 + self.indices.len() * 4 + self.sub_meshes.len() * 12
+        );
+        out.extend_from_slice(MAGIC);
+        write_u32(&mut out, MESH_CACHE_VERSION);
+        write_u64(&mut out, self.key);
+        for value in self.bounds.0.iter().chain(self.bounds.1.iter()) {
+            write_f32(&mut out, *value);
+        }
+        write_u32(&mut out, self.sub_meshes.len() as u32);
+        for sub_mesh in &self.sub_meshes {
+            write_u32(&mut out, sub_mesh.model);
+            write_u32(&mut out, sub_mesh.start);
+            write_u32(&mut out, sub_mesh.count);
+        }
+        write_u32(&mut out, self.vertices.len() as u32);
+        for vertex in &self.vertices {
+            for value in vertex.position.iter().chain(vertex.color.iter()) {
+                write_f32(&mut out, *value);
+            }
+        }
+        write_u32(&mut out, self.indices.len() as u32);
+        for index in &self.indices {
+            write_u32(&mut out, *index);
+        }
+        out
+    }
+
+    pub fn decode(bytes: &[u8]) -> Result<MeshCache> {
+        let mut reader = Reader { bytes, offset: 0 };
+        if reader.take(4)? != MAGIC {
+            return Err("Not a dot_vox mesh cache".into());
+        }
+        let version = reader.u32()?;
+        if version != MESH_CACHE_VERSION {
+            return Err(format!(
+                "Unsupported mesh cache version {}, expected {}", version, MESH_CACHE_VERSION
+            ).into());
+        }
+        let key = reader.u64()?;
+        let bounds = (
+            [reader.f32()?, reader.f32()?, reader.f32()?],
+            [reader.f32()?, reader.f32()?, reader.f32()?],
+        );
+        let sub_mesh_count = reader.u32()?;
+        let mut sub_meshes = Vec::new();
+        for _ in 0..sub_mesh_count {
+            sub_meshes.push(SubMesh {
+                model: reader.u32()?,
+                start: reader.u32()?,
+                count: reader.u32()?,
+            });
+        }
+        let vertex_count = reader.u32()?;
+        let mut vertices = Vec::new();
+        for _ in 0..vertex_count {
+            vertices.push(PosColor {
+                position: [reader.f32()?, reader.f32()?, reader.f32()?],
+                color: [reader.f32()?, reader.f32()?, reader.f32()?, reader.f32()?],
+            });
+        }
+        let index_count = reader.u32()?;
+        let mut indices = Vec::new();
+        for _ in 0..index_count {
+            let index = reader.u32()?;
+            if index >= vertex_count {
+                return Err(format!("Mesh cache index {} out of range", index).into());
+            }
+            indices.push(index);
+        }
+        for sub_mesh in &sub_meshes {
+            if sub_mesh.start as u64 + sub_mesh.count as u64 > index_count as u64 {
+                return Err(format!("Mesh cache sub-mesh {:?} out of range", sub_mesh).into());
+            }
+        }
+        Ok(MeshCache { key, bounds, vertices, indices, sub_meshes })
+    }
+}
+
+/// Imports a mesh previously written with `MeshCache::encode`.
+#[derive(Clone)]
+pub struct MeshCacheFormat;
+
+impl SimpleFormat<Mesh> for MeshCacheFormat {
+    const NAME: &'static str = "DOT_VOX_MESH_CACHE";
+    type Options = ();
+
+    fn import(&self, bytes: Vec<u8>, _: ()) -> Result<MeshData> {
+        Ok(MeshData::PosColor(MeshCache::decode(&bytes)?.to_triangles()))
+    }
+}
+
+/// 64 bit FNV-1a hash. Used instead of `DefaultHasher` so cache keys stay stable between Rust
+/// releases.
+pub fn fnv_hash(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+pub(crate) fn cache_path(dir: &Path, key: u64) -> PathBuf {
+    dir.join(format!("{:016x}.{}", key, MESH_CACHE_EXTENSION))
+}
+
+/// Reads the cache entry for `key`, returning `None` if it is missing or out of date.
+pub(crate) fn read_cache(dir: &Path, key: u64) -> Option<MeshCache> {
+    let path = cache_path(dir, key);
+    let bytes = fs::read(&path).ok()?;
+    match MeshCache::decode(&bytes) {
+        Ok(ref cache) if cache.key != key => None,
+        Ok(cache) => Some(cache),
+        Err(e) => {
+            debug!("Ignoring mesh cache {:?}: {}", path, e);
+            None
+        }
+    }
+}
+
+pub(crate) fn write_cache(dir: &Path, cache: &MeshCache) {
+    let path = cache_path(dir, cache.key);
+    if let Err(e) = fs::create_dir_all(dir).and_then(|_| fs::write(&path, cache.encode())) {
+        warn!("Unable to write mesh cache {:?}: {}", path, e);
+    }
+}
+
+fn vertex_key(vertex: &PosColor) -> [u32; 7] {
+    let (p, c) = (vertex.position, vertex.color);
+    [
+        p[0].to_bits(), p[1].to_bits(), p[2].to_bits(),
+        c[0].to_bits(), c[1].to_bits(), c[2].to_bits(), c[3].to_bits()
+    ]
+}
+
+fn bounds(vertices: &[PosColor]) -> ([f32; 3], [f32; 3]) {
+    if vertices.is_empty() {
+        return ([0.0; 3], [0.0; 3]);
+    }
+    vertices.iter().fold(([::std::f32::MAX; 3], [::std::f32::MIN; 3]), |(mut min, mut max), v| {
+        for axis in 0..3 {
+            min[axis] = min[axis].min(v.position[axis]);
+            max[axis] = max[axis].max(v.position[axis]);
+        }
+        (min, max)
+    })
+}
+
+pub(crate) fn write_u32(out: &mut Vec<u8>, value: u32) {
+    for shift in 0..4 {
+        out.push((value >> (shift * 8)) as u8);
+    }
+}
+
+pub(crate) fn write_u64(out: &mut Vec<u8>, value: u64) {
+    write_u32(out, value as u32);
+    write_u32(out, (value >> 32) as u32);
+}
+
+pub(crate) fn write_f32(out: &mut Vec<u8>, value: f32) {
+    write_u32(out, value.to_bits());
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.bytes.len() - self.offset < len {
+            return Err("Mesh cache is truncated".into());
+        }
+        let slice = &self.bytes[self.offset..self.offset + len];
+        self.offset += len;
+        Ok(slice)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let bytes = self.take(4)?;
+        Ok(bytes.iter().rev().fold(0, |value, byte| value << 8 | *byte as u32))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        let low = self.u32()? as u64;
+        let high = self.u32()? as u64;
+        Ok(high << 32 | low)
+    }
+
+    fn f32(&mut self) -> Result<f32> {
+        Ok(f32::from_bits(self.u32()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use avow::vec;
+    use super::*;
+
+    fn pos_color(position: [f32; 3], color: [f32; 4]) -> PosColor {
+        PosColor { position, color }
+    }
+
+    fn triangles() -> Vec<PosColor> {
+        let red = [1.0, 0.0, 0.0, 1.0];
+        let blue = [0.0, 0.0, 1.0, 1.0];
+        vec!(pos_color([0.0, 0.0, 0.0], red),
+             pos_color([1.0, 0.0, 0.0], red),
+             pos_color([1.0, 1.0, 0.0], red),
+             pos_color([1.0, 1.0, 0.0], red),
+             pos_color([0.0, 1.0, 0.0], red),
+             pos_color([0.0, 0.0, 0.0], red),
+             pos_color([0.0, 0.0, 0.0], blue),
+             pos_color([1.0, 0.0, -2.0], blue),
+             pos_color([1.0, 1.0, 0.0], blue))
+    }
+
+    #[test]
+    fn identical_vertices_are_shared() {
+        let cache = MeshCache::from_triangles(7, &[(0, 9)], &triangles());
+        assert_eq!(cache.vertices.len(), 7);
+        assert_eq!(cache.indices, vec!(0, 1, 2, 2, 3, 0, 4, 5, 6));
+        assert_eq!(cache.sub_meshes, vec!(SubMesh { model: 0, start: 0, count: 9 }));
+        assert_eq!(cache.bounds, ([0.0, 0.0, -2.0], [1.0, 1.0, 0.0]));
+        vec::are_eq(cache.to_triangles(), triangles());
+    }
+
+    #[test]
+    fn each_model_gets_its_own_sub_mesh() {
+        let cache = MeshCache::from_triangles(7, &[(3, 6), (1, 3)], &triangles());
+        assert_eq!(cache.sub_meshes, vec!(SubMesh { model: 3, start: 0, count: 6 },
+                                          SubMesh { model: 1, start: 6, count: 3 }));
+    }
+
+    #[test]
+    fn cache_survives_an_encode_decode_round_trip() {
+        let cache = MeshCache::from_triangles(0xdead_beef_cafe, &[(3, 6), (1, 3)], &triangles());
+        assert_eq!(MeshCache::decode(&cache.encode()).unwrap(), cache);
+    }
+
+    #[test]
+    fn truncated_or_foreign_data_is_rejected() {
+        let bytes = MeshCache::from_triangles(1, &[(0, 9)], &triangles()).encode();
+        for len in 0..bytes.len() {
+            assert!(MeshCache::decode(&bytes[..len]).is_err());
+        }
+        let mut newer = bytes.clone();
+        newer[4] = MESH_CACHE_VERSION as u8 + 1;
+        assert!(MeshCache::decode(&newer).is_err());
+    }
+
+    #[test]
+    fn fnv_hash_is_stable() {
+        assert_eq!(fnv_hash(b""), 0xcbf2_9ce4_8422_2325);
+        assert_eq!(fnv_hash(b"a"), 0xaf63_dc4c_8601_ec8c);
+    }
+}
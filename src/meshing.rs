@@ -0,0 +1,174 @@
+//! Meshing that only depends on `dot_vox`, for servers and tools that build voxel meshes without
+//! linking the renderer. Available with the default `amethyst` feature turned off.
+
+use grid::VoxelGrid;
+
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Set once a missing palette entry has been logged, so meshing a broken grid warns only once.
+static WARNED_MISSING_COLOR: AtomicBool = AtomicBool::new(false);
+
+/// Indexed triangle mesh in plain arrays, one entry per vertex in `positions`, `normals` and
+/// `colors`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MeshBuffers {
+    pub positions: Vec<[f32; 3]>,
+    pub normals: Vec<[f32; 3]>,
+    /// RGBA, each channel between 0 and 1.
+    pub colors: Vec<[f32; 4]>,
+    /// Three per triangle, wound counter-clockwise seen from outside.
+    pub indices: Vec<u32>,
+}
+
+/// Meshes a grid into a cube per voxel, like `mesh_model`, with the corners of each face shared
+/// between its two triangles. Models convert into a grid with `DenseGrid::from(&model)`.
+pub fn mesh_buffers<G: VoxelGrid>(grid: &G, palette: &[u32]) -> MeshBuffers {
+    let mut buffers = MeshBuffers::default();
+    for (voxel, index) in grid.voxels() {
+        let color = palette_to_rgba(palette, index as usize);
+        let first = buffers.positions.len();
+        for triangle in CUBE_OFFSETS.chunks(3) {
+            let normal = face_normal(triangle);
+            for &(x, y, z) in triangle {
+                let position = [voxel[0] as f32 + x, voxel[1] as f32 + y, voxel[2] as f32 + z];
+                let existing = (first..buffers.positions.len()).find(|&index| {
+                    buffers.positions[index] == position && buffers.normals[index] == normal
+                });
+                let index = existing.unwrap_or_else(|| {
+                    buffers.positions.push(position);
+                    buffers.normals.push(normal);
+                    buffers.colors.push(color);
+                    buffers.positions.len() - 1
+                });
+                buffers.indices.push(index as u32);
+            }
+        }
+    }
+    buffers
+}
+
+lazy_static! {
+    pub(crate) static ref CUBE_OFFSETS: Vec<(f32, f32, f32)> = vec!(
+        (-0.5, -0.5, -0.5),
+        (-0.5, -0.5, 0.5),
+        (-0.5, 0.5, 0.5),
+        (0.5, 0.5, -0.5),
+        (-0.5, -0.5, -0.5),
+        (-0.5, 0.5, -0.5),
+        (0.5, -0.5, 0.5),
+        (-0.5, -0.5, -0.5),
+        (0.5, -0.5, -0.5),
+        (0.5, 0.5, -0.5),
+        (0.5, -0.5, -0.5),
+        (-0.5, -0.5, -0.5),
+        (-0.5, -0.5, -0.5),
+        (-0.5, 0.5, 0.5),
+        (-0.5, 0.5, -0.5),
+        (0.5, -0.5, 0.5),
+        (-0.5, -0.5, 0.5),
+        (-0.5, -0.5, -0.5),
+        (-0.5, 0.5, 0.5),
+        (-0.5, -0.5, 0.5),
+        (0.5, -0.5, 0.5),
+        (0.5, 0.5, 0.5),
+        (0.5, -0.5, -0.5),
+        (0.5, 0.5, -0.5),
+        (0.5, -0.5, -0.5),
+        (0.5, 0.5, 0.5),
+        (0.5, -0.5, 0.5),
+        (0.5, 0.5, 0.5),
+        (0.5, 0.5, -0.5),
+        (-0.5, 0.5, -0.5),
+        (0.5, 0.5, 0.5),
+        (-0.5, 0.5, -0.5),
+        (-0.5, 0.5, 0.5),
+        (0.5, 0.5, 0.5),
+        (-0.5, 0.5, 0.5),
+        (0.5, -0.5, 0.5)
+);
+
+}
+
+/// Colour of a palette entry as RGBA between 0 and 1. Indices past the end of the palette, which
+/// `validate` rules out for imported files, give transparent black and log a warning the first
+/// time.
+pub fn palette_to_rgba(palette: &[u32], index: usize) -> [f32; 4] {
+    let color = match palette.get(index) {
+        Some(color) => color,
+        None => {
+            if !WARNED_MISSING_COLOR.swap(true, Ordering::Relaxed) {
+                warn!("Palette index {} is past the end of the {} colour palette, using \
+                       transparent black", index, palette.len());
+            }
+            &0
+        }
+    };
+    let (a, b, g, r) =
+        (color >> 24u32 & 0xFF, color >> 16u32 & 0xFF, color >> 8u32 & 0xFF, color & 0xFF);
+    [
+        r as f32 / 255.0,
+        g as f32 / 255.0,
+        b as f32 / 255.0,
+        a as f32 / 255.0
+    ]
+}
+
+/// Cube faces are axis aligned, so the normal is the axis all corners of a triangle share, pointing
+/// to the side they are on.
+pub(crate) fn face_normal(triangle: &[(f32, f32, f32)]) -> [f32; 3] {
+    let corners: Vec<[f32; 3]> = triangle.iter().map(|&(x, y, z)| [x, y, z]).collect();
+    let mut normal = [0.0; 3];
+    for axis in 0..3 {
+        if corners.iter().all(|corner| corner[axis] == corners[0][axis]) {
+            normal[axis] = corners[0][axis].signum();
+        }
+    }
+    normal
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dot_vox::{Model, Size, Voxel};
+    use grid::DenseGrid;
+
+    fn grid(voxels: Vec<Voxel>) -> DenseGrid {
+        DenseGrid::from(&Model { size: Size { x: 2, y: 1, z: 1 }, voxels })
+    }
+
+    #[test]
+    fn each_face_gets_its_own_four_corners() {
+        let buffers = mesh_buffers(&grid(vec!(Voxel { x: 1, y: 0, z: 0, i: 0 })), &[0xff00_00ff]);
+        assert_eq!(buffers.positions.len(), 24);
+        assert_eq!(buffers.normals.len(), 24);
+        assert_eq!(buffers.indices.len(), 36);
+        assert!(buffers.colors.iter().all(|&color| color == [1.0, 0.0, 0.0, 1.0]));
+        for (position, normal) in buffers.positions.iter().zip(&buffers.normals) {
+            let centred = [position[0] - 1.0, position[1], position[2]];
+            let along: f32 = (0..3).map(|axis| normal[axis] * centred[axis]).sum();
+            assert_eq!(along, 0.5);
+        }
+    }
+
+    #[test]
+    fn indexed_triangles_match_the_cube_offsets() {
+        let voxels = vec!(Voxel { x: 0, y: 0, z: 0, i: 0 }, Voxel { x: 1, y: 0, z: 0, i: 1 });
+        let buffers = mesh_buffers(&grid(voxels.clone()), &[0xffff_ffff; 2]);
+        let expected: Vec<[f32; 3]> = voxels.iter()
+            .flat_map(|voxel| CUBE_OFFSETS.iter().map(move |&(x, y, z)| {
+                [voxel.x as f32 + x, voxel.y as f32 + y, voxel.z as f32 + z]
+            }))
+            .collect();
+        let triangles: Vec<[f32; 3]> = buffers.indices.iter()
+            .map(|&index| buffers.positions[index as usize])
+            .collect();
+        assert_eq!(triangles, expected);
+    }
+
+    #[test]
+    fn missing_palette_entries_are_transparent_black() {
+        assert_eq!(palette_to_rgba(&[0xff00_00ff], 0), [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(palette_to_rgba(&[0xff00_00ff], 1), [0.0; 4]);
+        assert!(WARNED_MISSING_COLOR.load(Ordering::Relaxed));
+    }
+}
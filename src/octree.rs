@@ -0,0 +1,253 @@
+use grid::{VoxelGrid, VoxelPosition};
+
+use std::mem;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Node {
+    /// Every cell of the node holds the same value.
+    Uniform(Option<u8>),
+    /// Children ordered by their x, y and z halves as bits 0, 1 and 2.
+    Branch(Box<[Node; 8]>),
+}
+
+/// A grid backed by a sparse voxel octree. Regions that are uniformly empty or filled with one
+/// palette index collapse into a single node, so memory follows the amount of detail rather than
+/// the volume, suiting worlds over 1024³ that are mostly air.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OctreeGrid {
+    size: VoxelPosition,
+    /// Length of the sides of the cube the root covers, a power of two.
+    side: u32,
+    root: Node,
+    len: usize,
+}
+
+impl OctreeGrid {
+    /// An empty grid of `size` cells.
+    pub fn new(size: VoxelPosition) -> Self {
+        let longest = size.iter().cloned().max().unwrap_or(0).max(1);
+        OctreeGrid { size, side: longest.next_power_of_two(), root: Node::Uniform(None), len: 0 }
+    }
+
+    /// Number of nodes in the tree, counting the root.
+    pub fn node_count(&self) -> usize {
+        fn count(node: &Node) -> usize {
+            match *node {
+                Node::Uniform(_) => 1,
+                Node::Branch(ref children) => 1 + children.iter().map(count).sum::<usize>(),
+            }
+        }
+        count(&self.root)
+    }
+
+    /// Bytes used by the grid, including the nodes on the heap.
+    pub fn memory_usage(&self) -> usize {
+        let branches = (self.node_count() - 1) / 8;
+        mem::size_of::<Self>() + branches * mem::size_of::<[Node; 8]>()
+    }
+}
+
+impl VoxelGrid for OctreeGrid {
+    fn size(&self) -> VoxelPosition {
+        self.size
+    }
+
+    fn get(&self, position: VoxelPosition) -> Option<u8> {
+        if (0..3).any(|axis| position[axis] >= self.size[axis]) {
+            return None;
+        }
+        let (mut node, mut side, mut position) = (&self.root, self.side, position);
+        loop {
+            match *node {
+                Node::Uniform(value) => return value,
+                Node::Branch(ref children) => {
+                    side /= 2;
+                    node = &children[child_index(position, side)];
+                    position = within_child(position, side);
+                }
+            }
+        }
+    }
+
+    fn set(&mut self, position: VoxelPosition, index: Option<u8>) {
+        assert!((0..3).all(|axis| position[axis] < self.size[axis]),
+                "{:?} lies outside {:?}", position, self.size);
+        match (self.get(position).is_some(), index.is_some()) {
+            (false, true) => self.len += 1,
+            (true, false) => self.len -= 1,
+            _ => {}
+        }
+        set(&mut self.root, self.side, position, index);
+    }
+
+    fn voxels<'a>(&'a self) -> Box<dyn Iterator<Item = (VoxelPosition, u8)> + 'a> {
+        Box::new(Voxels { stack: vec!((&self.root, [0; 3], self.side)), fill: None })
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+}
+
+/// Sets the cell at `position` within `node`, covering a cube of `side` cells, splitting uniform
+/// nodes on the way down and collapsing children that became uniform on the way up.
+fn set(node: &mut Node, side: u32, position: VoxelPosition, index: Option<u8>) {
+    if side == 1 {
+        *node = Node::Uniform(index);
+        return;
+    }
+    if let Node::Uniform(value) = *node {
+        if value == index {
+            return;
+        }
+        let child = Node::Uniform(value);
+        *node = Node::Branch(Box::new([
+            child.clone(), child.clone(), child.clone(), child.clone(),
+            child.clone(), child.clone(), child.clone(), child,
+        ]));
+    }
+    let collapsed = match *node {
+        Node::Branch(ref mut children) => {
+            let half = side / 2;
+            {
+                let child = &mut children[child_index(position, half)];
+                set(child, half, within_child(position, half), index);
+            }
+            uniform_value(children)
+        }
+        Node::Uniform(_) => unreachable!(),
+    };
+    if let Some(value) = collapsed {
+        *node = Node::Uniform(value);
+    }
+}
+
+/// The value shared by all children, when they are all uniform.
+fn uniform_value(children: &[Node; 8]) -> Option<Option<u8>> {
+    let first = match children[0] {
+        Node::Uniform(value) => value,
+        Node::Branch(_) => return None,
+    };
+    if children.iter().all(|child| *child == Node::Uniform(first)) {
+        Some(first)
+    } else {
+        None
+    }
+}
+
+fn child_index(position: VoxelPosition, half: u32) -> usize {
+    (0..3).filter(|&axis| position[axis] >= half).map(|axis| 1 << axis).sum()
+}
+
+fn within_child(position: VoxelPosition, half: u32) -> VoxelPosition {
+    [position[0] % half, position[1] % half, position[2] % half]
+}
+
+/// Walks the tree depth first, expanding filled uniform nodes into their cells.
+struct Voxels<'a> {
+    stack: Vec<(&'a Node, VoxelPosition, u32)>,
+    /// Origin, side and palette index of the uniform node being expanded, and the next cell.
+    fill: Option<(VoxelPosition, u32, u8, u64)>,
+}
+
+impl<'a> Iterator for Voxels<'a> {
+    type Item = (VoxelPosition, u8);
+
+    fn next(&mut self) -> Option<(VoxelPosition, u8)> {
+        loop {
+            if let Some((origin, side, index, ref mut cell)) = self.fill {
+                let side = side as u64;
+                if *cell < side * side * side {
+                    let position = [
+                        origin[0] + (*cell % side) as u32,
+                        origin[1] + (*cell / side % side) as u32,
+                        origin[2] + (*cell / side / side) as u32,
+                    ];
+                    *cell += 1;
+                    return Some((position, index));
+                }
+            }
+            self.fill = None;
+            let (node, origin, side) = self.stack.pop()?;
+            match *node {
+                Node::Uniform(None) => {}
+                Node::Uniform(Some(index)) => self.fill = Some((origin, side, index, 0)),
+                Node::Branch(ref children) => {
+                    let half = side / 2;
+                    for (child, node) in children.iter().enumerate().rev() {
+                        let offset = |axis: usize| if child & 1 << axis != 0 { half } else { 0 };
+                        let origin = [
+                            origin[0] + offset(0),
+                            origin[1] + offset(1),
+                            origin[2] + offset(2),
+                        ];
+                        self.stack.push((node, origin, half));
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use grid::DenseGrid;
+
+    #[test]
+    fn cells_read_back_what_was_written() {
+        let mut octree = OctreeGrid::new([5, 3, 9]);
+        let mut dense = DenseGrid::new([5, 3, 9]);
+        let writes = [([0, 0, 0], 1), ([4, 2, 8], 2), ([3, 1, 4], 3), ([4, 2, 8], 4)];
+        for &(position, index) in &writes {
+            octree.set(position, Some(index));
+            dense.set(position, Some(index));
+        }
+        octree.set([0, 0, 0], None);
+        dense.set([0, 0, 0], None);
+
+        assert_eq!(octree.len(), 2);
+        assert_eq!(octree.get([4, 2, 8]), Some(4));
+        assert_eq!(octree.get([9, 9, 9]), None);
+        let mut voxels: Vec<_> = octree.voxels().collect();
+        voxels.sort();
+        let mut expected: Vec<_> = dense.voxels().collect();
+        expected.sort();
+        assert_eq!(voxels, expected);
+    }
+
+    #[test]
+    fn uniform_regions_collapse_into_one_node() {
+        let mut octree = OctreeGrid::new([4, 4, 4]);
+        for z in 0..2 {
+            for y in 0..2 {
+                for x in 0..2 {
+                    octree.set([x, y, z], Some(5));
+                }
+            }
+        }
+        // The filled octant is a single leaf beside seven empty ones.
+        assert_eq!(octree.node_count(), 9);
+        assert_eq!(octree.voxels().count(), 8);
+
+        octree.set([1, 1, 1], Some(6));
+        assert_eq!(octree.node_count(), 17);
+        octree.set([1, 1, 1], Some(5));
+        assert_eq!(octree.node_count(), 9);
+
+        for position in octree.voxels().map(|(position, _)| position).collect::<Vec<_>>() {
+            octree.set(position, None);
+        }
+        assert_eq!(octree.node_count(), 1);
+        assert!(octree.is_empty());
+    }
+
+    #[test]
+    fn mostly_empty_worlds_use_little_memory() {
+        let mut octree = OctreeGrid::new([4096, 4096, 4096]);
+        octree.set([1000, 2000, 3000], Some(1));
+        octree.set([4095, 0, 17], Some(2));
+        assert!(octree.memory_usage() < 4096);
+        assert_eq!(octree.get([1000, 2000, 3000]), Some(1));
+    }
+}
@@ -1,14 +1,16 @@
 use amethyst_assets::AssetStorage;
 use amethyst_core::GlobalTransform;
 use amethyst_core::cgmath::{Matrix4, SquareMatrix, Transform};
-use amethyst_renderer::{ActiveCamera, Attributes, Camera, Color, Effect, Encoder, Factory,
-                         Material, Mesh, MeshHandle, NewEffect, Position, Query, Visibility};
+use amethyst_renderer::{ActiveCamera, Attributes, Camera, Color, ColorMask, Effect, Encoder,
+                         Factory, Mesh, MeshHandle, NewEffect, Position, Query, Visibility, ALPHA};
 use amethyst_renderer::error::Result;
 use amethyst_renderer::pipe::DepthMode;
 use amethyst_renderer::pipe::pass::{Pass, PassData};
+use fog::{FogArgs, VoxelFog};
 use glsl_layout::*;
 
-use specs::{Join, Read, ReadStorage};
+use specs::{Component, DenseVecStorage, Entities, Join, Read, ReadStorage};
+use specs::world::EntitiesRes;
 
 use std::marker::PhantomData;
 
@@ -23,12 +25,67 @@ pub(crate) struct VertexArgs {
     model: mat4,
 }
 
-/// Draw mesh without lighting
+#[repr(C, align(16))]
+#[derive(Clone, Copy, Debug, Uniform)]
+pub(crate) struct FragmentArgs {
+    tint: vec4,
+    fog_color: vec4,
+    camera_position: vec3,
+    fog_falloff: int,
+    fog_start: float,
+    fog_density: float,
+}
+
+/// Colour multiplied into every voxel of an entity drawn by `DrawVoxels`, e.g. to flash an enemy
+/// red when hit, or to fade out a dying unit through the alpha channel. Entities without one are
+/// drawn untinted. Translucent entities are blended over the opaque ones, which are drawn first.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct VoxelTint(pub [f32; 4]);
+
+impl Default for VoxelTint {
+    fn default() -> Self {
+        VoxelTint([1.0; 4])
+    }
+}
+
+impl VoxelTint {
+    /// Whether the tint lets what is behind the entity show through.
+    pub(crate) fn is_translucent(&self) -> bool {
+        self.0[3] < 1.0
+    }
+}
+
+impl Component for VoxelTint {
+    type Storage = DenseVecStorage<Self>;
+}
+
+/// Problems a pass has already logged, so each is reported once rather than every frame.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub(crate) struct Warnings {
+    reported: Vec<&'static str>,
+}
+
+impl Warnings {
+    pub(crate) fn once<F: FnOnce() -> String>(&mut self, kind: &'static str, message: F) {
+        if !self.reported.contains(&kind) {
+            self.reported.push(kind);
+            warn!("{}", message());
+        }
+    }
+
+    pub(crate) fn reported(&self, kind: &'static str) -> bool {
+        self.reported.contains(&kind)
+    }
+}
+
+/// Draw mesh without lighting. Entities need a `MeshHandle` and a `GlobalTransform`. Applies
+/// `VoxelFog` when the resource is present.
 /// `V` is `VertexFormat`
 #[derive(Derivative, Clone, Debug, PartialEq)]
 #[derivative(Default(bound = "V: Query<(Position, Color)>, Self: Pass"))]
 pub struct DrawVoxels<V> {
     _pd: PhantomData<V>,
+    warnings: Warnings,
 }
 
 impl<V> DrawVoxels<V>
@@ -47,13 +104,15 @@ impl<'a, V> PassData<'a> for DrawVoxels<V>
         V: Query<(Position, Color)>,
 {
     type Data = (
+        Entities<'a>,
         Option<Read<'a, ActiveCamera>>,
         ReadStorage<'a, Camera>,
         Read<'a, AssetStorage<Mesh>>,
         Option<Read<'a, Visibility>>,
         ReadStorage<'a, MeshHandle>,
-        ReadStorage<'a, Material>,
         ReadStorage<'a, GlobalTransform>,
+        ReadStorage<'a, VoxelTint>,
+        Option<Read<'a, VoxelFog>>,
     );
 }
 
@@ -70,8 +129,18 @@ impl<V> Pass for DrawVoxels<V>
                 mem::size_of::<<VertexArgs as Uniform>::Std140>(),
                 1,
             )
+            .with_raw_constant_buffer(
+                "FragmentArgs",
+                mem::size_of::<<FragmentArgs as Uniform>::Std140>(),
+                1,
+            )
             .with_raw_vertex_buffer(V::QUERIED_ATTRIBUTES, V::size() as u8, 0);
-        builder.with_output("color", Some(DepthMode::LessEqualWrite));
+        builder.with_blended_output(
+            "color",
+            ColorMask::all(),
+            ALPHA,
+            Some(DepthMode::LessEqualWrite),
+        );
         builder.build()
     }
 
@@ -81,55 +150,77 @@ impl<V> Pass for DrawVoxels<V>
         effect: &mut Effect,
         _factory: Factory,
         (
+            entities,
             active,
             camera,
             mesh_storage,
             visibility,
             mesh,
-            material,
             global,
+            tint,
+            fog,
         ): <Self as PassData<'a>>::Data,
     ) {
         let camera = get_camera(active, &camera, &global);
+        let fog = FogArgs::new(fog.as_ref().map(|fog| &**fog), camera);
+        warn_missing_transforms(&mut self.warnings, &entities, &mesh, &global, "DrawVoxels");
 
-        match visibility {
-            None => for (mesh, material, global) in (&mesh, &material, &global).join() {
-                draw_mesh(
-                    encoder,
-                    effect,
-                    mesh_storage.get(mesh),
-                    Some(material),
-                    camera,
-                    Some(global),
-                    &[V::QUERIED_ATTRIBUTES],
-                );
-            },
-            Some(ref visibility) => {
-                for (mesh, material, global, _) in
-                    (&mesh, &material, &global, &visibility.visible_unordered).join()
-                    {
+        // Opaque entities go first, so that translucent ones blend over everything behind them.
+        for translucent in [false, true].iter().cloned() {
+            let drawn_now = |tint: Option<&VoxelTint>| is_translucent(tint) == translucent;
+            match visibility {
+                None => for (mesh, global, tint) in (&mesh, &global, tint.maybe()).join() {
+                    if drawn_now(tint) {
                         draw_mesh(
                             encoder,
                             effect,
+                            &mut self.warnings,
                             mesh_storage.get(mesh),
-                            Some(material),
                             camera,
                             Some(global),
-                            &[V::QUERIED_ATTRIBUTES]
+                            tint,
+                            &fog,
+                            &[V::QUERIED_ATTRIBUTES],
                         );
                     }
+                },
+                Some(ref visibility) => {
+                    for (mesh, global, tint, _) in
+                        (&mesh, &global, tint.maybe(), &visibility.visible_unordered).join()
+                        {
+                            if drawn_now(tint) {
+                                draw_mesh(
+                                    encoder,
+                                    effect,
+                                    &mut self.warnings,
+                                    mesh_storage.get(mesh),
+                                    camera,
+                                    Some(global),
+                                    tint,
+                                    &fog,
+                                    &[V::QUERIED_ATTRIBUTES]
+                                );
+                            }
+                        }
 
-                for entity in &visibility.visible_ordered {
-                    if let Some(mesh) = mesh.get(*entity) {
-                        draw_mesh(
-                            encoder,
-                            effect,
-                            mesh_storage.get(mesh),
-                            material.get(*entity),
-                            camera,
-                            global.get(*entity),
-                            &[V::QUERIED_ATTRIBUTES]
-                        );
+                    // Entities marked `Transparent` come sorted back to front, after the rest.
+                    if !translucent {
+                        continue;
+                    }
+                    for entity in &visibility.visible_ordered {
+                        if let Some(mesh) = mesh.get(*entity) {
+                            draw_mesh(
+                                encoder,
+                                effect,
+                                &mut self.warnings,
+                                mesh_storage.get(mesh),
+                                camera,
+                                global.get(*entity),
+                                tint.get(*entity),
+                                &fog,
+                                &[V::QUERIED_ATTRIBUTES]
+                            );
+                        }
                     }
                 }
             }
@@ -137,34 +228,65 @@ impl<V> Pass for DrawVoxels<V>
     }
 }
 
+/// Whether an entity with `tint` is drawn with the translucent entities.
+pub(crate) fn is_translucent(tint: Option<&VoxelTint>) -> bool {
+    tint.map_or(false, VoxelTint::is_translucent)
+}
+
 pub(crate) fn draw_mesh(
     encoder: &mut Encoder,
     effect: &mut Effect,
+    warnings: &mut Warnings,
     mesh: Option<&Mesh>,
-    material: Option<&Material>,
     camera: Option<(&Camera, &GlobalTransform)>,
     global: Option<&GlobalTransform>,
+    tint: Option<&VoxelTint>,
+    fog: &FogArgs,
     attributes: &[Attributes<'static>],
 ) {
     let mesh = match mesh {
         Some(mesh) => mesh,
         None => return,
     };
-    if material.is_none() || global.is_none() {
-        return;
-    }
+    let global = match global {
+        Some(global) => global,
+        None => return,
+    };
 
-    if !set_attribute_buffers(effect, mesh, attributes) {
+    if !set_attribute_buffers(effect, warnings, mesh, attributes) {
         effect.clear();
         return;
     }
 
-    set_vertex_args(effect, encoder, camera, global.unwrap());
+    set_vertex_args(effect, encoder, camera, global);
+    set_fragment_args(effect, encoder, tint, fog);
 
     effect.draw(mesh.slice(), encoder);
     effect.clear();
 }
 
+/// Logs once if any entity has a `MeshHandle` but no `GlobalTransform`, as such entities are
+/// skipped by `pass`.
+pub(crate) fn warn_missing_transforms(
+    warnings: &mut Warnings,
+    entities: &EntitiesRes,
+    mesh: &ReadStorage<MeshHandle>,
+    global: &ReadStorage<GlobalTransform>,
+    pass: &str,
+) {
+    const KIND: &str = "missing_transform";
+    if warnings.reported(KIND) {
+        return;
+    }
+    if let Some((entity, _, _)) = (entities, mesh, !global).join().next() {
+        warnings.once(KIND, || format!(
+            "{:?} has a MeshHandle but no GlobalTransform, so {} will not draw it. \
+             Further entities missing a GlobalTransform will not be reported.",
+            entity, pass
+        ));
+    }
+}
+
 pub(crate) fn get_camera<'a>(
     active: Option<Read<'a, ActiveCamera>>,
     camera: &'a ReadStorage<Camera>,
@@ -200,8 +322,30 @@ pub(crate) fn set_vertex_args(
     effect.update_constant_buffer("VertexArgs", &vertex_args.std140(), encoder);
 }
 
+pub(crate) fn set_fragment_args(
+    effect: &mut Effect,
+    encoder: &mut Encoder,
+    tint: Option<&VoxelTint>,
+    fog: &FogArgs,
+) {
+    effect.update_constant_buffer("FragmentArgs", &fragment_args(tint, fog).std140(), encoder);
+}
+
+/// The uniforms of the fragment shader for an entity drawn with `tint`, white without one.
+pub(crate) fn fragment_args(tint: Option<&VoxelTint>, fog: &FogArgs) -> FragmentArgs {
+    FragmentArgs {
+        tint: tint.cloned().unwrap_or_default().0.into(),
+        fog_color: fog.color.into(),
+        camera_position: fog.camera_position.into(),
+        fog_falloff: fog.falloff,
+        fog_start: fog.start,
+        fog_density: fog.density,
+    }
+}
+
 pub(crate) fn set_attribute_buffers(
     effect: &mut Effect,
+    warnings: &mut Warnings,
     mesh: &Mesh,
     attributes: &[Attributes<'static>],
 ) -> bool {
@@ -209,13 +353,35 @@ pub(crate) fn set_attribute_buffers(
         match mesh.buffer(attr) {
             Some(vbuf) => effect.data.vertex_bufs.push(vbuf.clone()),
             None => {
-                error!(
-                    "Required vertex attribute buffer with format {:?} missing in mesh",
+                warnings.once("missing_attributes", || format!(
+                    "Required vertex attribute buffer with format {:?} missing in mesh, so it \
+                     will not be drawn. Further meshes missing attributes will not be reported.",
                     attr
-                );
+                ));
                 return false;
             }
         }
     }
     true
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn the_tint_reaches_the_fragment_uniforms() {
+        let fog = FogArgs::new(None, None);
+        let red: vec4 = [1.0, 0.0, 0.0, 0.5].into();
+        assert_eq!(fragment_args(Some(&VoxelTint([1.0, 0.0, 0.0, 0.5])), &fog).tint, red);
+        let white: vec4 = [1.0; 4].into();
+        assert_eq!(fragment_args(None, &fog).tint, white);
+    }
+
+    #[test]
+    fn only_tints_with_alpha_below_one_are_translucent() {
+        assert!(!is_translucent(None));
+        assert!(!is_translucent(Some(&VoxelTint::default())));
+        assert!(is_translucent(Some(&VoxelTint([1.0, 1.0, 1.0, 0.25]))));
+    }
+}
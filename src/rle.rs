@@ -0,0 +1,279 @@
+use grid::{DenseGrid, VoxelGrid, VoxelPosition};
+
+use std::error;
+use std::fmt;
+
+/// Version of the `RleChunk` format written by this crate.
+pub const RLE_CHUNK_VERSION: u32 = 1;
+
+/// Most cells `RleChunk::decode` allocates a grid for, those of the largest MagicaVoxel model.
+pub const MAX_CHUNK_VOLUME: u64 = 256 * 256 * 256;
+
+/// A grid compressed for saving, e.g. a chunk of a world players have modified.
+///
+/// Cells are listed x fastest, then y, then z, as runs of equal values. Values refer to `palette`,
+/// which holds the distinct palette indices of the chunk, so a chunk only pays for the colours it
+/// uses. With the `serde` feature it serializes with any serde format; chunks saved by older
+/// versions are brought up to date with `upgrade`.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+pub struct RleChunk {
+    pub version: u32,
+    pub size: VoxelPosition,
+    /// Palette indices, as in `Voxel::i`, used by the chunk.
+    pub palette: Vec<u8>,
+    /// Number of cells and their value: 0 for empty cells, n for `palette[n - 1]`.
+    pub runs: Vec<(u32, u16)>,
+}
+
+impl RleChunk {
+    /// Compresses every cell of `grid`.
+    pub fn encode<G: VoxelGrid>(grid: &G) -> Self {
+        let size = grid.size();
+        let mut chunk = RleChunk {
+            version: RLE_CHUNK_VERSION,
+            size,
+            palette: Vec::new(),
+            runs: Vec::new(),
+        };
+        for z in 0..size[2] {
+            for y in 0..size[1] {
+                for x in 0..size[0] {
+                    let value = match grid.get([x, y, z]) {
+                        Some(index) => chunk.palette_value(index),
+                        None => 0,
+                    };
+                    if chunk.runs.last().map_or(false, |&(_, last)| last == value) {
+                        chunk.runs.last_mut().unwrap().0 += 1;
+                    } else {
+                        chunk.runs.push((1, value));
+                    }
+                }
+            }
+        }
+        chunk
+    }
+
+    fn palette_value(&mut self, index: u8) -> u16 {
+        let position = match self.palette.iter().position(|&entry| entry == index) {
+            Some(position) => position,
+            None => {
+                self.palette.push(index);
+                self.palette.len() - 1
+            }
+        };
+        position as u16 + 1
+    }
+
+    /// Writes the cells of the chunk into `grid`, which must be at least as large.
+    pub fn decode_into<G: VoxelGrid>(&self, grid: &mut G) -> Result<(), ChunkError> {
+        if self.version != RLE_CHUNK_VERSION {
+            return Err(ChunkError::UnsupportedVersion(self.version));
+        }
+        let size = grid.size();
+        if (0..3).any(|axis| size[axis] < self.size[axis]) {
+            return Err(ChunkError::GridTooSmall { chunk: self.size, grid: size });
+        }
+        let volume = self.volume();
+        let cells = self.runs.iter().map(|&(length, _)| length as u64).sum::<u64>();
+        if cells != volume {
+            return Err(ChunkError::RunsDontCoverChunk { cells, volume });
+        }
+        let palette_size = self.palette.len();
+        if let Some(&(_, value)) = self.runs.iter().find(|run| run.1 as usize > palette_size) {
+            return Err(ChunkError::MissingPaletteEntry(value));
+        }
+
+        let (width, depth) = (self.size[0] as u64, self.size[1] as u64);
+        let mut cell = 0u64;
+        for &(length, value) in &self.runs {
+            let index = match value {
+                0 => None,
+                value => Some(self.palette[value as usize - 1]),
+            };
+            for at in cell..cell + length as u64 {
+                let position = [at % width, at / width % depth, at / width / depth];
+                grid.set([position[0] as u32, position[1] as u32, position[2] as u32], index);
+            }
+            cell += length as u64;
+        }
+        Ok(())
+    }
+
+    /// Decompresses the chunk into a new dense grid, provided it holds at most
+    /// `MAX_CHUNK_VOLUME` cells.
+    pub fn decode(&self) -> Result<DenseGrid, ChunkError> {
+        let volume = self.volume();
+        if volume > MAX_CHUNK_VOLUME {
+            return Err(ChunkError::TooLarge(volume));
+        }
+        let mut grid = DenseGrid::new(self.size);
+        self.decode_into(&mut grid)?;
+        Ok(grid)
+    }
+
+    /// Number of cells in the chunk, saturating for sizes no grid could hold.
+    fn volume(&self) -> u64 {
+        self.size.iter().fold(1u64, |volume, &length| volume.saturating_mul(length as u64))
+    }
+
+    /// Brings a chunk saved by an older version of the format up to `RLE_CHUNK_VERSION`.
+    /// `migrate` is the hook for format version bumps: it is called with the chunk for as long as
+    /// it is behind, and must return it converted to a later version.
+    pub fn upgrade<F>(mut self, mut migrate: F) -> Result<RleChunk, ChunkError>
+    where
+        F: FnMut(RleChunk) -> Result<RleChunk, ChunkError>,
+    {
+        while self.version < RLE_CHUNK_VERSION {
+            let from = self.version;
+            self = migrate(self)?;
+            if self.version <= from {
+                return Err(ChunkError::MigrationStalled(from));
+            }
+        }
+        if self.version > RLE_CHUNK_VERSION {
+            return Err(ChunkError::UnsupportedVersion(self.version));
+        }
+        Ok(self)
+    }
+}
+
+/// Why an `RleChunk` could not be decoded or upgraded.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ChunkError {
+    /// The chunk was saved by another version of the format; older ones need an `upgrade`.
+    UnsupportedVersion(u32),
+    /// The runs hold a different number of cells than the size of the chunk.
+    RunsDontCoverChunk { cells: u64, volume: u64 },
+    /// A run refers to a value past the end of the chunk palette.
+    MissingPaletteEntry(u16),
+    /// A migration returned a chunk without bumping its version.
+    MigrationStalled(u32),
+    /// The chunk has more cells than `MAX_CHUNK_VOLUME`.
+    TooLarge(u64),
+    /// The grid given to `decode_into` can't hold the chunk.
+    GridTooSmall { chunk: VoxelPosition, grid: VoxelPosition },
+}
+
+impl fmt::Display for ChunkError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ChunkError::UnsupportedVersion(version) => {
+                write!(f, "Unsupported chunk version {}, expected {}", version, RLE_CHUNK_VERSION)
+            }
+            ChunkError::RunsDontCoverChunk { cells, volume } => {
+                write!(f, "Chunk runs hold {} cells, but the chunk has {}", cells, volume)
+            }
+            ChunkError::MissingPaletteEntry(value) => {
+                write!(f, "Chunk run refers to missing palette entry {}", value)
+            }
+            ChunkError::MigrationStalled(version) => {
+                write!(f, "Migrating chunk version {} didn't bump its version", version)
+            }
+            ChunkError::TooLarge(volume) => {
+                write!(f, "Chunk has {} cells, at most {} are allowed", volume, MAX_CHUNK_VOLUME)
+            }
+            ChunkError::GridTooSmall { chunk, grid } => {
+                write!(f, "Chunk of size {:?} doesn't fit a grid of size {:?}", chunk, grid)
+            }
+        }
+    }
+}
+
+impl error::Error for ChunkError {
+    fn description(&self) -> &str {
+        "Invalid voxel chunk"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use grid::SparseGrid;
+    #[cfg(feature = "serde")]
+    use serde_json;
+
+    fn world() -> SparseGrid {
+        let mut grid = SparseGrid::new([8, 4, 3]);
+        for x in 0..8 {
+            for y in 0..4 {
+                grid.set([x, y, 0], Some(200));
+            }
+        }
+        grid.set([3, 2, 1], Some(7));
+        grid.set([7, 3, 2], Some(200));
+        grid
+    }
+
+    fn sorted<G: VoxelGrid>(grid: &G) -> Vec<(VoxelPosition, u8)> {
+        let mut voxels: Vec<_> = grid.voxels().collect();
+        voxels.sort();
+        voxels
+    }
+
+    #[test]
+    fn chunks_survive_a_round_trip() {
+        let chunk = RleChunk::encode(&world());
+        assert_eq!(chunk.palette, vec!(200, 7));
+        // The filled floor is a single run.
+        assert_eq!(chunk.runs[0], (32, 1));
+        assert_eq!(sorted(&chunk.decode().unwrap()), sorted(&world()));
+
+        let mut sparse = SparseGrid::new([8, 4, 3]);
+        chunk.decode_into(&mut sparse).unwrap();
+        assert_eq!(sparse, world());
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn chunks_serialize_with_serde() {
+        let chunk = RleChunk::encode(&world());
+        let json = serde_json::to_string(&chunk).unwrap();
+        let loaded: RleChunk = serde_json::from_str(&json).unwrap();
+        assert_eq!(loaded, chunk);
+    }
+
+    #[test]
+    fn inconsistent_chunks_are_rejected() {
+        let mut chunk = RleChunk::encode(&world());
+        chunk.runs[0].0 += 1;
+        assert_eq!(chunk.decode(), Err(ChunkError::RunsDontCoverChunk { cells: 97, volume: 96 }));
+
+        let mut chunk = RleChunk::encode(&world());
+        chunk.runs[0].1 = 3;
+        assert_eq!(chunk.decode(), Err(ChunkError::MissingPaletteEntry(3)));
+    }
+
+    #[test]
+    fn chunks_must_fit_before_anything_is_allocated() {
+        let huge = RleChunk {
+            version: RLE_CHUNK_VERSION,
+            size: [u32::max_value(); 3],
+            palette: Vec::new(),
+            runs: vec!((u32::max_value(), 0)),
+        };
+        assert_eq!(huge.decode(), Err(ChunkError::TooLarge(u64::max_value())));
+
+        let mut small = SparseGrid::new([8, 4, 2]);
+        assert_eq!(RleChunk::encode(&world()).decode_into(&mut small),
+                   Err(ChunkError::GridTooSmall { chunk: [8, 4, 3], grid: [8, 4, 2] }));
+    }
+
+    #[test]
+    fn older_chunks_are_migrated_before_decoding() {
+        // A made up version 0 that stored palette indices one higher.
+        let mut old = RleChunk::encode(&world());
+        old.version = 0;
+        old.palette = old.palette.iter().map(|&index| index + 1).collect();
+        assert_eq!(old.decode(), Err(ChunkError::UnsupportedVersion(0)));
+
+        let upgraded = old.clone().upgrade(|mut chunk| {
+            chunk.palette = chunk.palette.iter().map(|&index| index - 1).collect();
+            chunk.version = 1;
+            Ok(chunk)
+        }).unwrap();
+        assert_eq!(sorted(&upgraded.decode().unwrap()), sorted(&world()));
+
+        assert_eq!(old.upgrade(Ok), Err(ChunkError::MigrationStalled(0)));
+    }
+}
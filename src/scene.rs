@@ -0,0 +1,545 @@
+use amethyst_assets::Result;
+use dot_vox::Size;
+use error::VoxError;
+
+use std::collections::{HashMap, HashSet};
+
+/// A layer of a MagicaVoxel scene.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Layer {
+    pub id: u32,
+    pub name: Option<String>,
+    pub hidden: bool,
+}
+
+/// Which models of a scene `DotVoxFormat` imports, by the layer they are on.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LayerFilter {
+    /// Every model, hidden or not.
+    All,
+    /// Models that are visible in MagicaVoxel, skipping hidden layers and hidden nodes.
+    Visible,
+    /// Models on the layers with these names, whether hidden or not.
+    Named(Vec<String>),
+}
+
+impl LayerFilter {
+    pub fn accepts(&self, scene: &Scene, model: &SceneModel) -> bool {
+        match *self {
+            LayerFilter::All => true,
+            LayerFilter::Visible => !model.hidden,
+            LayerFilter::Named(ref names) => model.layer
+                .and_then(|id| scene.layer(id))
+                .and_then(|layer| layer.name.as_ref())
+                .map_or(false, |name| names.contains(name)),
+        }
+    }
+}
+
+/// A model placed in a scene.
+#[derive(Clone, Debug, PartialEq)]
+pub struct SceneModel {
+    /// Index into the models of the file.
+    pub model: usize,
+    /// Position of the model's centre, summed over the transform nodes above it. Rotations are
+    /// not applied.
+    pub translation: [i32; 3],
+    pub layer: Option<u32>,
+    /// Set when the model's layer or any transform node above it is hidden.
+    pub hidden: bool,
+    /// `_name` of the closest named transform node above the model.
+    pub name: Option<String>,
+}
+
+impl SceneModel {
+    /// Where voxel (0, 0, 0) of the model lands, given the model's size. MagicaVoxel places the
+    /// centre of a model at its translation.
+    pub fn origin(&self, size: &Size) -> [f32; 3] {
+        let size = [size.x, size.y, size.z];
+        let mut origin = [0.0; 3];
+        for axis in 0..3 {
+            origin[axis] = (self.translation[axis] - size[axis] as i32 / 2) as f32;
+        }
+        origin
+    }
+}
+
+type Dict = HashMap<String, String>;
+
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) enum Node {
+    Transform { attributes: Dict, child: u32, layer: Option<u32>, translation: [i32; 3] },
+    Group { children: Vec<u32> },
+    Shape { models: Vec<u32> },
+}
+
+/// The scene graph and layers of a `.vox` file, which `dot_vox` skips over.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Scene {
+    pub(crate) nodes: HashMap<u32, Node>,
+    pub layers: Vec<Layer>,
+    model_count: usize,
+}
+
+impl Scene {
+    pub fn parse(bytes: &[u8]) -> Result<Scene> {
+        let mut reader = Reader { bytes, offset: 0 };
+        if reader.take(4)? != b"VOX " {
+            return Err(VoxError::Malformed("Not a valid MagicaVoxel .vox file".to_string()).into());
+        }
+        reader.u32()?;
+        if reader.take(4)? != b"MAIN" {
+            return Err(VoxError::Malformed("Missing MAIN chunk".to_string()).into());
+        }
+        let content_size = reader.u32()? as usize;
+        let children_size = reader.u32()? as usize;
+        if content_size + children_size > bytes.len() - reader.offset {
+            return Err(VoxError::Malformed(".vox file is truncated".to_string()).into());
+        }
+        reader.take(content_size)?;
+        let mut scene = Scene::default();
+        while reader.offset < bytes.len() {
+            let id = reader.take(4)?;
+            let content_size = reader.u32()? as usize;
+            reader.u32()?;
+            let mut content = Reader { bytes: reader.take(content_size)?, offset: 0 };
+            // Children follow their parent's content, so walking chunks in order visits them.
+            match id {
+                b"SIZE" => scene.model_count += 1,
+                b"nTRN" => {
+                    let id = content.u32()?;
+                    let attributes = content.dict()?;
+                    let child = content.u32()?;
+                    content.u32()?;
+                    let layer = content.i32()?;
+                    let frames = content.u32()?;
+                    let translation = match frames {
+                        0 => [0; 3],
+                        _ => parse_translation(content.dict()?.get("_t"))?,
+                    };
+                    let layer = if layer < 0 { None } else { Some(layer as u32) };
+                    let transform = Node::Transform { attributes, child, layer, translation };
+                    scene.nodes.insert(id, transform);
+                }
+                b"nGRP" => {
+                    let id = content.u32()?;
+                    content.dict()?;
+                    let count = content.u32()?;
+                    let children = (0..count).map(|_| content.u32()).collect::<Result<_>>()?;
+                    scene.nodes.insert(id, Node::Group { children });
+                }
+                b"nSHP" => {
+                    let id = content.u32()?;
+                    content.dict()?;
+                    let count = content.u32()?;
+                    let mut models = Vec::new();
+                    for _ in 0..count {
+                        models.push(content.u32()?);
+                        content.dict()?;
+                    }
+                    scene.nodes.insert(id, Node::Shape { models });
+                }
+                b"LAYR" => {
+                    let id = content.u32()?;
+                    let attributes = content.dict()?;
+                    scene.layers.push(Layer {
+                        id,
+                        name: attributes.get("_name").cloned(),
+                        hidden: is_hidden(&attributes),
+                    });
+                }
+                _ => {}
+            }
+        }
+        scene.check_graph()?;
+        Ok(scene)
+    }
+
+    /// Rejects scene graphs that reach a node more than once, through a cycle or a group listing
+    /// a child twice, or that nest deeper than `MAX_DEPTH`. Either would make `models` loop, blow
+    /// up exponentially or overflow the stack on a crafted file.
+    fn check_graph(&self) -> ::std::result::Result<(), VoxError> {
+        let mut reached = HashSet::new();
+        let mut pending = vec!((0, 0));
+        while let Some((id, depth)) = pending.pop() {
+            let node = match self.nodes.get(&id) {
+                Some(node) => node,
+                None => continue,
+            };
+            if !reached.insert(id) {
+                return Err(VoxError::Malformed(format!("Scene node {} is reached twice", id)));
+            }
+            if depth > MAX_DEPTH {
+                return Err(VoxError::Malformed(format!("Scene nests deeper than {}", MAX_DEPTH)));
+            }
+            match *node {
+                Node::Transform { child, .. } => pending.push((child, depth + 1)),
+                Node::Group { ref children } => {
+                    pending.extend(children.iter().map(|&child| (child, depth + 1)))
+                }
+                Node::Shape { .. } => {}
+            }
+        }
+        Ok(())
+    }
+
+    pub fn layer(&self, id: u32) -> Option<&Layer> {
+        self.layers.iter().find(|layer| layer.id == id)
+    }
+
+    /// Every model placed in the scene, in scene graph order. Files without a scene graph place
+    /// each of their models once at the origin.
+    pub fn models(&self) -> Vec<SceneModel> {
+        if !self.nodes.contains_key(&0) {
+            return (0..self.model_count)
+                .map(|model| SceneModel {
+                    model,
+                    translation: [0; 3],
+                    layer: None,
+                    hidden: false,
+                    name: None,
+                })
+                .collect();
+        }
+        let mut models = Vec::new();
+        let root = Placement { translation: [0; 3], layer: None, hidden: false, name: None };
+        self.visit(0, &root, &mut models);
+        models
+    }
+
+    /// Walks the graph from `id`, which `check_graph` made sure is a tree of bounded depth.
+    fn visit(&self, id: u32, parent: &Placement, models: &mut Vec<SceneModel>) {
+        match self.nodes.get(&id) {
+            Some(&Node::Transform { ref attributes, child, layer, translation }) => {
+                let hidden = parent.hidden || is_hidden(attributes) ||
+                    layer.and_then(|id| self.layer(id)).map_or(false, |layer| layer.hidden);
+                let placement = Placement {
+                    translation: [
+                        parent.translation[0] + translation[0],
+                        parent.translation[1] + translation[1],
+                        parent.translation[2] + translation[2],
+                    ],
+                    layer: layer.or(parent.layer),
+                    hidden,
+                    name: attributes.get("_name").cloned().or_else(|| parent.name.clone()),
+                };
+                self.visit(child, &placement, models);
+            }
+            Some(&Node::Group { ref children }) => for child in children {
+                self.visit(*child, parent, models);
+            },
+            Some(&Node::Shape { models: ref shape_models }) => for model in shape_models {
+                models.push(SceneModel {
+                    model: *model as usize,
+                    translation: parent.translation,
+                    layer: parent.layer,
+                    hidden: parent.hidden,
+                    name: parent.name.clone(),
+                });
+            },
+            None => {}
+        }
+    }
+}
+
+/// Deepest nesting of scene nodes accepted, far beyond what MagicaVoxel creates.
+const MAX_DEPTH: usize = 256;
+
+/// What a node inherits from the transforms above it.
+struct Placement {
+    translation: [i32; 3],
+    layer: Option<u32>,
+    hidden: bool,
+    name: Option<String>,
+}
+
+fn is_hidden(attributes: &Dict) -> bool {
+    attributes.get("_hidden").map_or(false, |hidden| hidden == "1")
+}
+
+fn parse_translation(value: Option<&String>) -> Result<[i32; 3]> {
+    let value = match value {
+        Some(value) => value,
+        None => return Ok([0; 3]),
+    };
+    let parts: Vec<i32> = value.split_whitespace()
+        .map(|part| part.parse())
+        .collect::<::std::result::Result<_, _>>()
+        .map_err(|_| VoxError::Malformed(format!("Invalid translation {:?}", value)))?;
+    if parts.len() != 3 {
+        return Err(VoxError::Malformed(format!("Invalid translation {:?}", value)).into());
+    }
+    Ok([parts[0], parts[1], parts[2]])
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        if self.bytes.len() - self.offset < len {
+            return Err(VoxError::Malformed(".vox chunk is truncated".to_string()).into());
+        }
+        let slice = &self.bytes[self.offset..self.offset + len];
+        self.offset += len;
+        Ok(slice)
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        let bytes = self.take(4)?;
+        Ok(bytes.iter().rev().fold(0, |value, byte| value << 8 | *byte as u32))
+    }
+
+    fn i32(&mut self) -> Result<i32> {
+        Ok(self.u32()? as i32)
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let len = self.u32()? as usize;
+        Ok(String::from_utf8_lossy(self.take(len)?).into_owned())
+    }
+
+    fn dict(&mut self) -> Result<Dict> {
+        let count = self.u32()?;
+        let mut dict = HashMap::new();
+        for _ in 0..count {
+            let key = self.string()?;
+            let value = self.string()?;
+            dict.insert(key, value);
+        }
+        Ok(dict)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    const PLACEHOLDER: &'static [u8] = include_bytes!("../resources/mesh/placeholder.vox");
+
+    /// Builds `.vox` files with a scene graph, one transform and shape per model under a root
+    /// group.
+    pub(crate) struct SceneBuilder {
+        children: Vec<u8>,
+        transforms: Vec<(u32, [i32; 3], bool, Option<&'static str>)>,
+        layers: Vec<(&'static str, bool)>,
+    }
+
+    impl SceneBuilder {
+        pub(crate) fn new() -> Self {
+            SceneBuilder { children: Vec::new(), transforms: Vec::new(), layers: Vec::new() }
+        }
+
+        pub(crate) fn layer(mut self, name: &'static str, hidden: bool) -> Self {
+            self.layers.push((name, hidden));
+            self
+        }
+
+        /// Adds a 1x1x1 model of palette index `i`, placed at `translation` on `layer`.
+        pub(crate) fn model(
+            mut self,
+            i: u8,
+            layer: u32,
+            translation: [i32; 3],
+            hidden: bool,
+        ) -> Self {
+            chunk(&mut self.children, b"SIZE", &words(&[1, 1, 1]));
+            chunk(&mut self.children, b"XYZI", &words(&[1, (i as u32 + 1) << 24]));
+            self.transforms.push((layer, translation, hidden, None));
+            self
+        }
+
+        /// Names the transform node of the last model added.
+        pub(crate) fn named(mut self, name: &'static str) -> Self {
+            self.transforms.last_mut().unwrap().3 = Some(name);
+            self
+        }
+
+        pub(crate) fn build(self) -> Vec<u8> {
+            let mut children = self.children;
+            let count = self.transforms.len() as u32;
+            let mut root = words(&[0, 0, 1]);
+            root.extend(words(&[u32::max_value(), u32::max_value(), 1, 0]));
+            chunk(&mut children, b"nTRN", &root);
+            let mut group = words(&[1, 0, count]);
+            group.extend(words(&(0..count).map(|n| 2 + n * 2).collect::<Vec<_>>()));
+            chunk(&mut children, b"nGRP", &group);
+            for (n, &(layer, translation, hidden, name)) in self.transforms.iter().enumerate() {
+                let n = n as u32;
+                let mut transform = words(&[2 + n * 2]);
+                let mut attributes = Vec::new();
+                if hidden {
+                    attributes.push(("_hidden", "1"));
+                }
+                if let Some(name) = name {
+                    attributes.push(("_name", name));
+                }
+                transform.extend(dict(&attributes));
+                transform.extend(words(&[3 + n * 2, u32::max_value(), layer, 1]));
+                let t = format!("{} {} {}", translation[0], translation[1], translation[2]);
+                transform.extend(dict(&[("_t", &t)]));
+                chunk(&mut children, b"nTRN", &transform);
+                chunk(&mut children, b"nSHP", &words(&[3 + n * 2, 0, 1, n, 0]));
+            }
+            for (id, &(name, hidden)) in self.layers.iter().enumerate() {
+                let mut layer = words(&[id as u32]);
+                let hidden = if hidden { "1" } else { "0" };
+                layer.extend(dict(&[("_name", name), ("_hidden", hidden)]));
+                layer.extend(words(&[u32::max_value()]));
+                chunk(&mut children, b"LAYR", &layer);
+            }
+            let mut file = b"VOX \x96\0\0\0MAIN\0\0\0\0".to_vec();
+            file.extend(words(&[children.len() as u32]));
+            file.extend(children);
+            file
+        }
+    }
+
+    fn words(values: &[u32]) -> Vec<u8> {
+        values.iter()
+            .flat_map(|value| (0..4).map(move |shift| (value >> (shift * 8)) as u8))
+            .collect()
+    }
+
+    fn dict(entries: &[(&str, &str)]) -> Vec<u8> {
+        let mut out = words(&[entries.len() as u32]);
+        for &(key, value) in entries {
+            for string in &[key, value] {
+                out.extend(words(&[string.len() as u32]));
+                out.extend(string.bytes());
+            }
+        }
+        out
+    }
+
+    fn chunk(out: &mut Vec<u8>, id: &[u8], content: &[u8]) {
+        out.extend_from_slice(id);
+        out.extend(words(&[content.len() as u32, 0]));
+        out.extend_from_slice(content);
+    }
+
+    #[test]
+    fn placeholder_scene_has_one_model_and_eight_layers() {
+        let scene = Scene::parse(PLACEHOLDER).unwrap();
+        assert_eq!(scene.layers.len(), 8);
+        assert_eq!(scene.layers[3], Layer { id: 3, name: Some("3".to_string()), hidden: false });
+        assert_eq!(
+            scene.models(),
+            vec!(SceneModel {
+                model: 0,
+                translation: [0, 0, 1],
+                layer: Some(0),
+                hidden: false,
+                name: None,
+            })
+        );
+    }
+
+    #[test]
+    fn hidden_layers_and_nodes_hide_their_models() {
+        let bytes = SceneBuilder::new()
+            .layer("intact", false)
+            .layer("damaged", true)
+            .model(1, 0, [0, 0, 0], false)
+            .model(2, 1, [4, 0, 0], false)
+            .model(3, 0, [-4, 2, 0], true)
+            .build();
+        let scene = Scene::parse(&bytes).unwrap();
+        let models = scene.models();
+        assert_eq!(models.iter().map(|model| model.hidden).collect::<Vec<_>>(),
+                   vec!(false, true, true));
+        assert_eq!(models[2].translation, [-4, 2, 0]);
+
+        let visible: Vec<usize> = models.iter()
+            .filter(|model| LayerFilter::Visible.accepts(&scene, model))
+            .map(|model| model.model)
+            .collect();
+        assert_eq!(visible, vec!(0));
+
+        let damaged: Vec<usize> = models.iter()
+            .filter(|model| LayerFilter::Named(vec!("damaged".to_string())).accepts(&scene, model))
+            .map(|model| model.model)
+            .collect();
+        assert_eq!(damaged, vec!(1));
+    }
+
+    #[test]
+    fn files_without_a_scene_place_every_model_at_the_origin() {
+        let mut bytes = SceneBuilder::new().model(1, 0, [0; 3], false).build();
+        // Cut the scene chunks, keeping only SIZE and XYZI.
+        bytes.truncate(20 + 24 + 20);
+        let children = bytes.len() as u32 - 20;
+        bytes[16..20].copy_from_slice(&words(&[children]));
+        let scene = Scene::parse(&bytes).unwrap();
+        assert_eq!(
+            scene.models(),
+            vec!(SceneModel {
+                model: 0,
+                translation: [0; 3],
+                layer: None,
+                hidden: false,
+                name: None,
+            })
+        );
+    }
+
+    #[test]
+    fn models_are_named_after_their_transform_node() {
+        let bytes = SceneBuilder::new()
+            .model(1, 0, [0; 3], false).named("door_left")
+            .model(2, 0, [3, 0, 0], false)
+            .model(3, 0, [0, 5, 0], false).named("spawn_point")
+            .build();
+        let names: Vec<Option<String>> = Scene::parse(&bytes).unwrap().models().into_iter()
+            .map(|model| model.name)
+            .collect();
+        let name = |name: &str| Some(name.to_string());
+        assert_eq!(names, vec!(name("door_left"), None, name("spawn_point")));
+    }
+
+    #[test]
+    fn models_are_centred_on_their_translation() {
+        let model = SceneModel {
+            model: 0,
+            translation: [0, 10, -3],
+            layer: None,
+            hidden: false,
+            name: None,
+        };
+        assert_eq!(model.origin(&Size { x: 4, y: 3, z: 1 }), [-2.0, 9.0, -3.0]);
+    }
+
+    #[test]
+    fn truncated_scenes_are_rejected() {
+        let bytes = SceneBuilder::new().layer("a", false).model(1, 0, [0; 3], false).build();
+        for len in 8..bytes.len() {
+            let error = Scene::parse(&bytes[..len]).unwrap_err();
+            assert!(format!("{:?}", error).contains("Malformed"), "{:?}", error);
+        }
+    }
+
+    #[test]
+    fn graphs_reaching_a_node_twice_are_rejected() {
+        let file = |group: &[u32]| {
+            let mut children = Vec::new();
+            let mut root = words(&[0, 0, 1]);
+            root.extend(words(&[u32::max_value(), u32::max_value(), 1, 0]));
+            chunk(&mut children, b"nTRN", &root);
+            let mut content = words(&[1, 0, group.len() as u32]);
+            content.extend(words(group));
+            chunk(&mut children, b"nGRP", &content);
+            chunk(&mut children, b"nSHP", &words(&[2, 0, 1, 0, 0]));
+            let mut file = b"VOX \x96\0\0\0MAIN\0\0\0\0".to_vec();
+            file.extend(words(&[children.len() as u32]));
+            file.extend(children);
+            file
+        };
+        assert_eq!(Scene::parse(&file(&[2])).unwrap().models().len(), 1);
+        for group in &[&[1][..], &[2, 2][..], &[0][..]] {
+            let error = Scene::parse(&file(group)).unwrap_err();
+            assert!(format!("{:?}", error).contains("reached twice"), "{:?}", group);
+        }
+    }
+}
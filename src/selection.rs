@@ -0,0 +1,349 @@
+use amethyst_assets::{AssetStorage, Result as AssetResult, SimpleFormat};
+use amethyst_core::GlobalTransform;
+use amethyst_core::cgmath::{Matrix4, Vector3};
+use amethyst_renderer::{ActiveCamera, Attributes, Camera, Color, Effect, Encoder, Factory, Mesh,
+                         MeshData, MeshHandle, NewEffect, PosColor, Position, Query, VertexFormat};
+use amethyst_renderer::error::Result;
+use amethyst_renderer::pipe::DepthMode;
+use amethyst_renderer::pipe::pass::{Pass, PassData};
+use debug::cell_lines;
+use dot_vox::{Model, Size, Voxel};
+use dot_vox_format::{self, DotVoxOptions};
+use fog::FogArgs;
+use gfx_core::Primitive;
+use glsl_layout::*;
+use meshing::CUBE_OFFSETS;
+use renderer::{draw_mesh, get_camera, FragmentArgs, VertexArgs, VoxelTint, Warnings};
+
+use specs::{Component, DenseVecStorage, Entity, Join, Read, ReadStorage};
+
+use std::marker::PhantomData;
+
+static VERT_SRC: &[u8] = include_bytes!("shaders/vertex/basic.glsl");
+static FRAG_SRC: &[u8] = include_bytes!("shaders/fragment/flat.glsl");
+
+/// How far the highlight cube sticks out of the voxel it surrounds, so it isn't hidden by the
+/// voxel's own faces.
+const HIGHLIGHT_SCALE: f32 = 1.02;
+
+/// Marks an entity as selected. `DrawVoxelOutline` draws `outline`, a hull imported with
+/// `DotVoxOutlineFormat`, in `color` around it.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VoxelSelected {
+    pub outline: MeshHandle,
+    pub color: [f32; 4],
+}
+
+impl Component for VoxelSelected {
+    type Storage = DenseVecStorage<Self>;
+}
+
+/// Resource picking a single voxel for `DrawVoxelHighlight` to outline. Coordinates are in the
+/// voxel space of the entity's model.
+#[derive(Clone, Debug, PartialEq)]
+pub struct VoxelHighlight {
+    pub target: Option<(Entity, [u32; 3])>,
+    pub color: [f32; 4],
+}
+
+impl Default for VoxelHighlight {
+    fn default() -> Self {
+        VoxelHighlight {
+            target: None,
+            color: [1.0, 1.0, 0.0, 1.0],
+        }
+    }
+}
+
+/// Options accepted by `DotVoxOutlineFormat`.
+#[derive(Clone, Debug)]
+pub struct OutlineOptions {
+    /// Outline width in voxels.
+    pub width: f32,
+    /// Options of the mesh being outlined, so the hull matches it.
+    pub mesh: DotVoxOptions,
+}
+
+/// Imports a `.vox` file as the outline hull used by `VoxelSelected`.
+#[derive(Clone)]
+pub struct DotVoxOutlineFormat;
+
+impl SimpleFormat<Mesh> for DotVoxOutlineFormat {
+    const NAME: &'static str = "MAGICAVOXEL_DOT_VOX_OUTLINE";
+    type Options = OutlineOptions;
+
+    fn import(&self, bytes: Vec<u8>, options: OutlineOptions) -> AssetResult<MeshData> {
+        let (models, _) = dot_vox_format::selected_models(&bytes, &options.mesh)?;
+        let (models, origins): (Vec<_>, Vec<_>) = models.into_iter()
+            .map(|(_, model, origin)| (dot_vox_format::prepare(model, &options.mesh), origin))
+            .unzip();
+        dot_vox_format::check_cube_vertices(&models, &options.mesh)?;
+        let factor = options.mesh.lod.map(|lod| lod.factor.max(1)).unwrap_or(1);
+        let mut hull = Vec::new();
+        for (model, origin) in models.iter().zip(origins) {
+            let mut model_hull = outline_hull(model, options.width / factor as f32);
+            dot_vox_format::to_source_space(&mut model_hull, &options.mesh);
+            for vertex in &mut model_hull {
+                for axis in 0..3 {
+                    vertex.position[axis] += origin[axis];
+                }
+            }
+            hull.extend(model_hull);
+        }
+        Ok(MeshData::PosColor(hull))
+    }
+}
+
+/// The model with every voxel grown by `width` on each side, in white so the outline colour can
+/// be applied as a tint.
+pub fn outline_hull(model: &Model, width: f32) -> Vec<PosColor> {
+    let scale = 1.0 + 2.0 * width;
+    model.voxels.iter()
+        .flat_map(|voxel| {
+            CUBE_OFFSETS.iter().map(move |&(x, y, z)| PosColor {
+                position: [
+                    voxel.x as f32 + x * scale,
+                    voxel.y as f32 + y * scale,
+                    voxel.z as f32 + z * scale,
+                ],
+                color: [1.0; 4],
+            })
+        })
+        .collect()
+}
+
+/// Draws the outline of entities with a `VoxelSelected` and a `GlobalTransform`.
+///
+/// Add it to the same `Stage` just before `DrawVoxels`: the hull is drawn without writing depth,
+/// so the model drawn afterwards covers all of it but the rim.
+/// `V` is `VertexFormat`
+#[derive(Derivative, Clone, Debug, PartialEq)]
+#[derivative(Default(bound = "V: Query<(Position, Color)>, Self: Pass"))]
+pub struct DrawVoxelOutline<V> {
+    _pd: PhantomData<V>,
+    warnings: Warnings,
+}
+
+impl<V> DrawVoxelOutline<V>
+    where
+        V: Query<(Position, Color)>,
+        Self: Pass,
+{
+    /// Create instance of `DrawVoxelOutline` pass
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl<'a, V> PassData<'a> for DrawVoxelOutline<V>
+    where
+        V: Query<(Position, Color)>,
+{
+    type Data = (
+        Option<Read<'a, ActiveCamera>>,
+        ReadStorage<'a, Camera>,
+        Read<'a, AssetStorage<Mesh>>,
+        ReadStorage<'a, VoxelSelected>,
+        ReadStorage<'a, GlobalTransform>,
+    );
+}
+
+impl<V> Pass for DrawVoxelOutline<V>
+    where
+        V: Query<(Position, Color)>,
+{
+    fn compile(&mut self, effect: NewEffect) -> Result<Effect> {
+        compile(effect, V::QUERIED_ATTRIBUTES, V::size(), Primitive::TriangleList)
+    }
+
+    fn apply<'a, 'b: 'a>(
+        &'a mut self,
+        encoder: &mut Encoder,
+        effect: &mut Effect,
+        _factory: Factory,
+        (active, camera, mesh_storage, selected, global): <Self as PassData<'a>>::Data,
+    ) {
+        let camera = get_camera(active, &camera, &global);
+
+        for (selected, global) in (&selected, &global).join() {
+            draw_mesh(
+                encoder,
+                effect,
+                &mut self.warnings,
+                mesh_storage.get(&selected.outline),
+                camera,
+                Some(global),
+                Some(&VoxelTint(selected.color)),
+                &FogArgs::default(),
+                &[V::QUERIED_ATTRIBUTES],
+            );
+        }
+    }
+}
+
+/// Outlines the voxel picked by the `VoxelHighlight` resource. Add it to the same `Stage` after
+/// `DrawVoxels`.
+#[derive(Derivative)]
+#[derivative(Debug, Default)]
+pub struct DrawVoxelHighlight {
+    #[derivative(Debug = "ignore")]
+    cube: Option<Mesh>,
+    warnings: Warnings,
+}
+
+impl DrawVoxelHighlight {
+    /// Create instance of `DrawVoxelHighlight` pass
+    pub fn new() -> Self {
+        Default::default()
+    }
+}
+
+impl<'a> PassData<'a> for DrawVoxelHighlight {
+    type Data = (
+        Option<Read<'a, ActiveCamera>>,
+        ReadStorage<'a, Camera>,
+        Read<'a, VoxelHighlight>,
+        ReadStorage<'a, GlobalTransform>,
+    );
+}
+
+impl Pass for DrawVoxelHighlight {
+    fn compile(&mut self, effect: NewEffect) -> Result<Effect> {
+        compile(
+            effect,
+            <PosColor as Query<(Position, Color)>>::QUERIED_ATTRIBUTES,
+            PosColor::size(),
+            Primitive::LineList,
+        )
+    }
+
+    fn apply<'a, 'b: 'a>(
+        &'a mut self,
+        encoder: &mut Encoder,
+        effect: &mut Effect,
+        mut factory: Factory,
+        (active, camera, highlight, global): <Self as PassData<'a>>::Data,
+    ) {
+        let (entity, [x, y, z]) = match highlight.target {
+            Some(target) => target,
+            None => return,
+        };
+        let transform = match global.get(entity) {
+            Some(transform) => GlobalTransform(
+                transform.0 *
+                    Matrix4::from_translation(Vector3::new(x as f32, y as f32, z as f32)) *
+                    Matrix4::from_scale(HIGHLIGHT_SCALE)
+            ),
+            None => return,
+        };
+        if self.cube.is_none() {
+            match Mesh::build(unit_cube_lines()).build(&mut factory) {
+                Ok(cube) => self.cube = Some(cube),
+                Err(e) => {
+                    self.warnings.once("highlight_mesh", || {
+                        format!("Unable to create the voxel highlight mesh: {}", e)
+                    });
+                    return;
+                }
+            }
+        }
+
+        let camera = get_camera(active, &camera, &global);
+        draw_mesh(
+            encoder,
+            effect,
+            &mut self.warnings,
+            self.cube.as_ref(),
+            camera,
+            Some(&transform),
+            Some(&VoxelTint(highlight.color)),
+            &FogArgs::default(),
+            &[<PosColor as Query<(Position, Color)>>::QUERIED_ATTRIBUTES],
+        );
+    }
+}
+
+fn compile(
+    effect: NewEffect,
+    attributes: Attributes<'static>,
+    stride: usize,
+    primitive: Primitive,
+) -> Result<Effect> {
+    use std::mem;
+    let mut builder = effect.simple(VERT_SRC, FRAG_SRC);
+    builder
+        .with_raw_constant_buffer(
+            "VertexArgs",
+            mem::size_of::<<VertexArgs as Uniform>::Std140>(),
+            1,
+        )
+        .with_raw_constant_buffer(
+            "FragmentArgs",
+            mem::size_of::<<FragmentArgs as Uniform>::Std140>(),
+            1,
+        )
+        .with_raw_vertex_buffer(attributes, stride as u8, 0)
+        .with_primitive_type(primitive);
+    builder.with_output("color", Some(DepthMode::LessEqualTest));
+    builder.build()
+}
+
+/// The edges of the voxel cell at the origin, in white so the highlight colour can be applied as
+/// a tint.
+fn unit_cube_lines() -> Vec<PosColor> {
+    let model = Model {
+        size: Size { x: 1, y: 1, z: 1 },
+        voxels: vec!(Voxel { x: 0, y: 0, z: 0, i: 0 }),
+    };
+    cell_lines(&model).into_iter()
+        .map(|line| PosColor { color: [1.0; 4], ..line })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use scene::tests::SceneBuilder;
+    use scene::LayerFilter;
+
+    #[test]
+    fn hull_grows_every_voxel_by_the_width() {
+        let model = Model {
+            size: Size { x: 3, y: 1, z: 1 },
+            voxels: vec!(Voxel { x: 2, y: 0, z: 0, i: 4 }),
+        };
+        let hull = outline_hull(&model, 0.25);
+        assert_eq!(hull.len(), 36);
+        for vertex in &hull {
+            assert_eq!(vertex.color, [1.0; 4]);
+            assert_eq!((vertex.position[0] - 2.0).abs(), 0.75);
+            assert_eq!(vertex.position[1].abs(), 0.75);
+        }
+    }
+
+    #[test]
+    fn layered_outlines_surround_every_placed_model() {
+        let scene = SceneBuilder::new()
+            .layer("", false)
+            .model(1, 0, [0; 3], false)
+            .model(2, 0, [4, 0, 0], false)
+            .build();
+        let mesh = DotVoxOptions { layers: Some(LayerFilter::All), ..Default::default() };
+        let options = OutlineOptions { width: 0.0, mesh: mesh.clone() };
+        let hull = match DotVoxOutlineFormat.import(scene.clone(), options).unwrap() {
+            MeshData::PosColor(vertices) => vertices,
+            result => panic!("Expected an outline hull, received {:?}", result),
+        };
+        let coloured = dot_vox_format::mesh(&scene, &mesh).unwrap();
+        assert_eq!(hull.iter().map(|vertex| vertex.position).collect::<Vec<_>>(),
+                   coloured.iter().map(|vertex| vertex.position).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn highlight_cube_is_a_white_unit_cell() {
+        let lines = unit_cube_lines();
+        assert_eq!(lines.len(), 24);
+        assert!(lines.iter().all(|vertex| vertex.color == [1.0; 4]));
+        assert!(lines.iter().all(|vertex| vertex.position.iter().all(|p| p.abs() == 0.5)));
+    }
+}
@@ -1,5 +1,14 @@
 #version 150 core
 
+layout (std140) uniform FragmentArgs {
+    uniform vec4 tint;
+    uniform vec4 fog_color;
+    uniform vec3 camera_position;
+    uniform int fog_falloff;
+    uniform float fog_start;
+    uniform float fog_density;
+};
+
 in VertexData {
     vec3 position;
     vec4 color;
@@ -7,6 +16,17 @@ in VertexData {
 
 out vec4 color;
 
+float fog_visibility() {
+    float depth = max(distance(vertex.position, camera_position) - fog_start, 0.0);
+    if (fog_falloff == 1) {
+        return clamp(1.0 - depth * fog_density, 0.0, 1.0);
+    } else if (fog_falloff == 2) {
+        return clamp(exp(-depth * fog_density), 0.0, 1.0);
+    }
+    return 1.0;
+}
+
 void main() {
-    color = vertex.color;
+    color = vertex.color * tint;
+    color.rgb = mix(fog_color.rgb, color.rgb, fog_visibility());
 }
@@ -0,0 +1,27 @@
+#version 150 core
+
+layout (std140) uniform ViewArgs {
+    uniform mat4 proj;
+    uniform mat4 view;
+};
+
+in vec3 position;
+in vec4 color;
+in vec4 model_col0;
+in vec4 model_col1;
+in vec4 model_col2;
+in vec4 model_col3;
+in vec4 tint;
+
+out VertexData {
+    vec3 position;
+    vec4 color;
+} vertex;
+
+void main() {
+    mat4 model = mat4(model_col0, model_col1, model_col2, model_col3);
+    vec4 vertex_position = model * vec4(position, 1.0);
+    vertex.position = vertex_position.xyz;
+    vertex.color = color * tint;
+    gl_Position = proj * view * vertex_position;
+}
@@ -0,0 +1,104 @@
+use amethyst_renderer::PosColor;
+
+/// Fixed brightness per face direction, baked into vertex colours at import for a cheap lit look
+/// without any lights. MagicaVoxel models are z-up, so `top` faces +z.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FaceShading {
+    pub top: f32,
+    /// Faces pointing along x.
+    pub x_sides: f32,
+    /// Faces pointing along y.
+    pub y_sides: f32,
+    pub bottom: f32,
+}
+
+impl Default for FaceShading {
+    fn default() -> Self {
+        FaceShading {
+            top: 1.0,
+            x_sides: 0.8,
+            y_sides: 0.6,
+            bottom: 0.5,
+        }
+    }
+}
+
+impl FaceShading {
+    /// Brightness of a face with the given unit normal. Faces that don't line up with an axis
+    /// blend the brightness of the directions they lean towards.
+    pub fn brightness(&self, normal: [f32; 3]) -> f32 {
+        let vertical = if normal[2] > 0.0 { self.top } else { self.bottom };
+        normal[0] * normal[0] * self.x_sides +
+            normal[1] * normal[1] * self.y_sides +
+            normal[2] * normal[2] * vertical
+    }
+}
+
+/// Multiplies the colour of every triangle by the brightness of the direction it faces. Alpha
+/// is left alone.
+pub fn shade_faces(triangles: &mut [PosColor], shading: &FaceShading) {
+    for triangle in triangles.chunks_mut(3).filter(|t| t.len() == 3) {
+        let brightness = match triangle_normal(triangle) {
+            Some(normal) => shading.brightness(normal),
+            None => continue,
+        };
+        for vertex in triangle {
+            for channel in 0..3 {
+                vertex.color[channel] *= brightness;
+            }
+        }
+    }
+}
+
+/// Unit normal of a counter-clockwise triangle, or `None` if it has no area.
+pub(crate) fn triangle_normal(triangle: &[PosColor]) -> Option<[f32; 3]> {
+    let (a, b, c) = (triangle[0].position, triangle[1].position, triangle[2].position);
+    let u = [b[0] - a[0], b[1] - a[1], b[2] - a[2]];
+    let v = [c[0] - a[0], c[1] - a[1], c[2] - a[2]];
+    let normal = [
+        u[1] * v[2] - u[2] * v[1],
+        u[2] * v[0] - u[0] * v[2],
+        u[0] * v[1] - u[1] * v[0],
+    ];
+    let length = (normal[0] * normal[0] + normal[1] * normal[1] + normal[2] * normal[2]).sqrt();
+    if length == 0.0 {
+        return None;
+    }
+    Some([normal[0] / length, normal[1] / length, normal[2] / length])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn triangle(positions: [[f32; 3]; 3]) -> Vec<PosColor> {
+        positions.iter()
+            .map(|&position| PosColor { position, color: [1.0, 0.5, 1.0, 0.25] })
+            .collect()
+    }
+
+    #[test]
+    fn each_face_direction_gets_its_own_brightness() {
+        let shading = FaceShading::default();
+        let cases = vec!(
+            ([[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]], 1.0),
+            ([[0.0, 0.0, 0.0], [0.0, 1.0, 0.0], [1.0, 0.0, 0.0]], 0.5),
+            ([[0.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]], 0.8),
+            ([[0.0, 0.0, 0.0], [0.0, 0.0, 1.0], [1.0, 0.0, 0.0]], 0.6),
+        );
+        for (positions, brightness) in cases {
+            let mut shaded = triangle(positions);
+            shade_faces(&mut shaded, &shading);
+            for vertex in &shaded {
+                assert_eq!(vertex.color, [brightness, 0.5 * brightness, brightness, 0.25]);
+            }
+        }
+    }
+
+    #[test]
+    fn degenerate_triangles_are_left_alone() {
+        let mut flat = triangle([[0.0, 0.0, 0.0], [1.0, 1.0, 1.0], [2.0, 2.0, 2.0]]);
+        shade_faces(&mut flat, &FaceShading::default());
+        assert_eq!(flat, triangle([[0.0, 0.0, 0.0], [1.0, 1.0, 1.0], [2.0, 2.0, 2.0]]));
+    }
+}
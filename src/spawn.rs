@@ -0,0 +1,206 @@
+use amethyst_assets::{AssetStorage, Loader, Result};
+use amethyst_core::{GlobalTransform, Transform};
+use amethyst_core::cgmath::Vector3;
+use amethyst_core::transform::Parent;
+use amethyst_renderer::{Mesh, MeshHandle};
+use dot_vox_format::{self, DotVoxFormat, DotVoxOptions};
+use error::VoxError;
+use scene::{LayerFilter, Scene};
+
+use specs::{Builder, Component, DenseVecStorage, Entity, World};
+
+use std::collections::HashMap;
+
+/// Name of the MagicaVoxel transform node an entity was spawned from by `spawn_scene`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Named(pub String);
+
+impl Component for Named {
+    type Storage = DenseVecStorage<Self>;
+}
+
+/// Resource finding the entities `spawn_scene` created for named nodes, e.g. "door_left" or
+/// "spawn_point".
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct VoxNames {
+    entities: HashMap<String, Vec<Entity>>,
+}
+
+impl VoxNames {
+    /// The first entity spawned with `name`.
+    pub fn get(&self, name: &str) -> Option<Entity> {
+        self.get_all(name).first().cloned()
+    }
+
+    /// Every entity spawned with `name`, in scene order.
+    pub fn get_all(&self, name: &str) -> &[Entity] {
+        self.entities.get(name).map(|entities| &entities[..]).unwrap_or(&[])
+    }
+
+    fn insert(&mut self, name: String, entity: Entity) {
+        self.entities.entry(name).or_insert_with(Vec::new).push(entity);
+    }
+}
+
+/// Spawns an entity per model of the scene in `bytes`, the contents of the `.vox` file at `path`.
+///
+/// Models are filtered by `options.layers`, defaulting to the visible ones, checked together
+/// against `options.limits`, and each is meshed with `options` through the `Loader`. Entities get
+/// a `MeshHandle`, a `Transform` placing them as in MagicaVoxel, a `GlobalTransform`, a `Parent`
+/// when `parent` is given, and a `Named` component plus a `VoxNames` entry when their node is
+/// named. Rotations are not applied.
+pub fn spawn_scene(
+    world: &mut World,
+    path: &str,
+    bytes: &[u8],
+    options: &DotVoxOptions,
+    parent: Option<Entity>,
+) -> Result<Vec<Entity>> {
+    let data = dot_vox_format::load(bytes)?;
+    let scene = Scene::parse(bytes)?;
+    let filter = options.layers.clone().unwrap_or(LayerFilter::Visible);
+    let placed: Vec<_> = scene.models().into_iter()
+        .filter(|placed| filter.accepts(&scene, placed))
+        .collect();
+    let mut models = Vec::with_capacity(placed.len());
+    for placed in &placed {
+        models.push(data.models.get(placed.model).ok_or(VoxError::MissingModel {
+            index: placed.model,
+            count: data.models.len(),
+        })?);
+    }
+    options.limits.check_models(&models)?;
+
+    let mut meshes: HashMap<usize, MeshHandle> = HashMap::new();
+    {
+        let loader = world.read_resource::<Loader>();
+        let storage = world.read_resource::<AssetStorage<Mesh>>();
+        for placed in &placed {
+            meshes.entry(placed.model).or_insert_with(|| {
+                let options = DotVoxOptions {
+                    model: placed.model,
+                    layers: None,
+                    ..options.clone()
+                };
+                loader.load(path, DotVoxFormat, options, (), &storage)
+            });
+        }
+    }
+
+    world.register::<Named>();
+    world.res.entry::<VoxNames>().or_insert_with(VoxNames::default);
+    let mut entities = Vec::with_capacity(placed.len());
+    for placed in placed {
+        let origin = placed.origin(&data.models[placed.model].size);
+        let mut transform = Transform::default();
+        transform.translation = Vector3::new(origin[0], origin[1], origin[2]);
+        let mut builder = world.create_entity()
+            .with(meshes[&placed.model].clone())
+            .with(transform)
+            .with(GlobalTransform::default());
+        if let Some(parent) = parent {
+            builder = builder.with(Parent { entity: parent });
+        }
+        if let Some(ref name) = placed.name {
+            builder = builder.with(Named(name.clone()));
+        }
+        let entity = builder.build();
+        if let Some(name) = placed.name {
+            world.write_resource::<VoxNames>().insert(name, entity);
+        }
+        entities.push(entity);
+    }
+    Ok(entities)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use amethyst_core::rayon::ThreadPoolBuilder;
+    use limits::ImportLimits;
+    use scene::tests::SceneBuilder;
+
+    use std::sync::Arc;
+
+    /// A world with what `spawn_scene` needs. Meshes are only queued on the `Loader`, never
+    /// processed, so no renderer is involved.
+    fn world() -> World {
+        let mut world = World::new();
+        let pool = Arc::new(ThreadPoolBuilder::new().num_threads(1).build().unwrap());
+        world.add_resource(Loader::new(".", pool));
+        world.add_resource(AssetStorage::<Mesh>::new());
+        world.register::<MeshHandle>();
+        world.register::<Transform>();
+        world.register::<GlobalTransform>();
+        world.register::<Parent>();
+        world
+    }
+
+    fn scene() -> Vec<u8> {
+        SceneBuilder::new()
+            .model(1, 0, [0; 3], false).named("door")
+            .model(2, 0, [4, 0, 0], false)
+            .model(3, 0, [0, 6, 0], false).named("door")
+            .model(4, 0, [0, 0, 8], false).named("spawn_point")
+            .build()
+    }
+
+    #[test]
+    fn every_model_becomes_an_entity_found_by_its_name() {
+        let mut world = world();
+        let root = world.create_entity().build();
+        let entities = spawn_scene(&mut world, "doors.vox", &scene(), &Default::default(),
+                                   Some(root)).unwrap();
+        assert_eq!(entities.len(), 4);
+
+        let named = world.read_storage::<Named>();
+        assert_eq!(named.get(entities[0]), Some(&Named("door".to_string())));
+        assert_eq!(named.get(entities[1]), None);
+        assert_eq!(named.get(entities[3]), Some(&Named("spawn_point".to_string())));
+
+        let names = world.read_resource::<VoxNames>();
+        assert_eq!(names.get_all("door"), &[entities[0], entities[2]][..]);
+        assert_eq!(names.get("spawn_point"), Some(entities[3]));
+        assert_eq!(names.get("window"), None);
+
+        let parents = world.read_storage::<Parent>();
+        for &entity in &entities {
+            assert_eq!(parents.get(entity).map(|parent| parent.entity), Some(root));
+        }
+        let transforms = world.read_storage::<Transform>();
+        let translation = transforms.get(entities[1]).unwrap().translation;
+        assert_eq!(translation, Vector3::new(4.0, 0.0, 0.0));
+        assert!(world.read_storage::<MeshHandle>().get(entities[1]).is_some());
+    }
+
+    #[test]
+    fn scenes_spawned_without_a_parent_are_roots() {
+        let mut world = world();
+        let first = spawn_scene(&mut world, "doors.vox", &scene(), &Default::default(), None)
+            .unwrap();
+        let second = spawn_scene(&mut world, "doors.vox", &scene(), &Default::default(), None)
+            .unwrap();
+
+        let parents = world.read_storage::<Parent>();
+        assert!(first.iter().chain(&second).all(|&entity| parents.get(entity).is_none()));
+        // Names accumulate over scenes.
+        let names = world.read_resource::<VoxNames>();
+        assert_eq!(names.get_all("spawn_point"), &[first[3], second[3]][..]);
+    }
+
+    #[test]
+    fn limits_apply_to_the_whole_scene() {
+        let mut world = world();
+        let limited = |limits| DotVoxOptions { limits, ..Default::default() };
+        let models = limited(ImportLimits { max_models: Some(3), ..Default::default() });
+        assert!(spawn_scene(&mut world, "doors.vox", &scene(), &models, None).is_err());
+        let voxels = limited(ImportLimits { max_voxels: Some(3), ..Default::default() });
+        assert!(spawn_scene(&mut world, "doors.vox", &scene(), &voxels, None).is_err());
+        let enough = limited(ImportLimits {
+            max_models: Some(4),
+            max_voxels: Some(4),
+            ..Default::default()
+        });
+        assert_eq!(spawn_scene(&mut world, "doors.vox", &scene(), &enough, None).unwrap().len(), 4);
+    }
+}
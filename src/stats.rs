@@ -0,0 +1,111 @@
+use amethyst_renderer::PosColor;
+use dot_vox::Model;
+
+use std::collections::HashSet;
+use std::time::Duration;
+
+/// What an import produced and how long it took, from `import_with_stats`. `DotVoxFormat` logs
+/// it at debug level.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ImportStats {
+    /// Voxels meshed, after markers are removed and LODs downsampled.
+    pub voxels: usize,
+    /// Square faces in the mesh, two triangles each.
+    pub faces_emitted: usize,
+    /// Voxel faces that didn't make it into the mesh, e.g. hidden ones skipped by surface nets.
+    pub faces_culled: usize,
+    /// Distinct vertices, as in an indexed mesh.
+    pub vertices: usize,
+    /// Indices of the triangle list.
+    pub indices: usize,
+    /// Distinct palette indices used by the meshed voxels.
+    pub palette_indices: usize,
+    /// Smallest and largest corner of the mesh, or `None` when it is empty.
+    pub bounds: Option<([f32; 3], [f32; 3])>,
+    /// Time spent reading and preparing the models.
+    pub parse_time: Duration,
+    /// Time spent turning them into triangles.
+    pub mesh_time: Duration,
+}
+
+impl ImportStats {
+    pub(crate) fn measure(
+        models: &[Model],
+        triangles: &[PosColor],
+        parse_time: Duration,
+        mesh_time: Duration,
+    ) -> Self {
+        let voxels: usize = models.iter().map(|model| model.voxels.len()).sum();
+        let faces_emitted = triangles.len() / 6;
+        let palette_indices: HashSet<u8> = models.iter()
+            .flat_map(|model| model.voxels.iter().map(|voxel| voxel.i))
+            .collect();
+        let vertices: HashSet<_> = triangles.iter()
+            .map(|vertex| {
+                let (p, c) = (vertex.position, vertex.color);
+                [p[0].to_bits(), p[1].to_bits(), p[2].to_bits(),
+                 c[0].to_bits(), c[1].to_bits(), c[2].to_bits(), c[3].to_bits()]
+            })
+            .collect();
+        ImportStats {
+            voxels,
+            faces_emitted,
+            faces_culled: (voxels * 6).saturating_sub(faces_emitted),
+            vertices: vertices.len(),
+            indices: triangles.len(),
+            palette_indices: palette_indices.len(),
+            bounds: bounds(triangles),
+            parse_time,
+            mesh_time,
+        }
+    }
+}
+
+fn bounds(triangles: &[PosColor]) -> Option<([f32; 3], [f32; 3])> {
+    let first = triangles.first()?.position;
+    Some(triangles.iter().fold((first, first), |(mut min, mut max), vertex| {
+        for axis in 0..3 {
+            min[axis] = min[axis].min(vertex.position[axis]);
+            max[axis] = max[axis].max(vertex.position[axis]);
+        }
+        (min, max)
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dot_vox::{Size, Voxel};
+    use dot_vox_format::mesh_model;
+
+    #[test]
+    fn stats_count_what_the_mesh_holds() {
+        let model = Model {
+            size: Size { x: 2, y: 1, z: 1 },
+            voxels: vec!(Voxel { x: 0, y: 0, z: 0, i: 3 }, Voxel { x: 1, y: 0, z: 0, i: 3 }),
+        };
+        let triangles = mesh_model(&model, &[0xffff_ffff; 256]);
+        let (parse_time, mesh_time) = (Duration::new(1, 0), Duration::new(2, 0));
+        let stats = ImportStats::measure(&[model], &triangles, parse_time, mesh_time);
+        assert_eq!(stats.voxels, 2);
+        assert_eq!(stats.faces_emitted, 12);
+        assert_eq!(stats.faces_culled, 0);
+        // The touching faces share their corners.
+        assert_eq!(stats.vertices, 12);
+        assert_eq!(stats.indices, 72);
+        assert_eq!(stats.palette_indices, 1);
+        assert_eq!(stats.bounds, Some(([-0.5, -0.5, -0.5], [1.5, 0.5, 0.5])));
+        assert_eq!(stats.parse_time, Duration::new(1, 0));
+    }
+
+    #[test]
+    fn faces_missing_from_the_mesh_count_as_culled() {
+        let model = Model {
+            size: Size { x: 1, y: 1, z: 1 },
+            voxels: vec!(Voxel { x: 0, y: 0, z: 0, i: 0 }),
+        };
+        let stats = ImportStats::measure(&[model], &[], Duration::default(), Duration::default());
+        assert_eq!(stats.faces_culled, 6);
+        assert_eq!(stats.bounds, None);
+    }
+}
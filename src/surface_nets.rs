@@ -0,0 +1,197 @@
+use amethyst_renderer::PosColor;
+use dot_vox::Model;
+use grid::{DenseGrid, VoxelGrid};
+use meshing::palette_to_rgba;
+
+use std::collections::{BTreeSet, HashMap};
+
+type Point = (i32, i32, i32);
+
+/// How `DotVoxFormat` turns voxels into triangles.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Extractor {
+    /// A cube per voxel, keeping the blocky look of the model.
+    Cubes,
+    /// A smooth surface following the outline of the voxels, for terrain and organic shapes.
+    SurfaceNets,
+}
+
+impl Default for Extractor {
+    fn default() -> Self {
+        Extractor::Cubes
+    }
+}
+
+/// Extracts a smooth, closed surface from the occupancy of a model with naive surface nets.
+///
+/// Samples sit at voxel centres, so the surface lies in the same space as the cube mesh. Every
+/// cell between eight samples that straddles the surface gets one vertex, at the mean of the
+/// crossings on its edges, coloured like the closest solid sample.
+pub fn surface_nets(model: &Model, palette: &[u32]) -> Vec<PosColor> {
+    let solid = DenseGrid::from(model);
+    let mut voxels: Vec<Point> = solid.voxels()
+        .map(|(position, _)| (position[0] as i32, position[1] as i32, position[2] as i32))
+        .collect();
+    voxels.sort();
+    let cells: BTreeSet<Point> = voxels.iter()
+        .flat_map(|&(x, y, z)| {
+            (0..8).map(move |bits| (x - (bits & 1), y - (bits >> 1 & 1), z - (bits >> 2 & 1)))
+        })
+        .collect();
+    let vertices: HashMap<Point, PosColor> = cells.into_iter()
+        .filter_map(|cell| cell_vertex(cell, &solid, palette).map(|vertex| (cell, vertex)))
+        .collect();
+
+    let mut triangles = Vec::new();
+    for voxel in voxels {
+        for axis in 0..3 {
+            for &direction in &[1, -1] {
+                let neighbour = offset(voxel, axis, direction);
+                if index_at(&solid, neighbour).is_some() {
+                    continue;
+                }
+                let start = if direction > 0 { voxel } else { neighbour };
+                let (b, c) = ((axis + 1) % 3, (axis + 2) % 3);
+                let quad = [
+                    start,
+                    offset(start, b, -1),
+                    offset(offset(start, b, -1), c, -1),
+                    offset(start, c, -1),
+                ];
+                let quad: Vec<PosColor> = quad.iter().map(|cell| vertices[cell]).collect();
+                // Wound to face away from the solid side.
+                let order = if direction > 0 { [0, 1, 2, 0, 2, 3] } else { [0, 2, 1, 0, 3, 2] };
+                triangles.extend(order.iter().map(|&corner| quad[corner]));
+            }
+        }
+    }
+    triangles
+}
+
+fn cell_vertex(cell: Point, solid: &DenseGrid, palette: &[u32]) -> Option<PosColor> {
+    let corner = |bits: i32| {
+        (cell.0 + (bits & 1), cell.1 + (bits >> 1 & 1), cell.2 + (bits >> 2 & 1))
+    };
+    let inside = |bits: i32| index_at(solid, corner(bits)).is_some();
+    let solid_corners = (0..8).filter(|&bits| inside(bits)).count();
+    if solid_corners == 0 || solid_corners == 8 {
+        return None;
+    }
+
+    let mut sum = [0.0; 3];
+    let mut crossings = 0.0;
+    for start in 0..8 {
+        for axis in 0..3 {
+            let end = start | 1 << axis;
+            if start & 1 << axis == 0 && inside(start) != inside(end) {
+                let (a, b) = (corner(start), corner(end));
+                sum[0] += (a.0 + b.0) as f32 / 2.0;
+                sum[1] += (a.1 + b.1) as f32 / 2.0;
+                sum[2] += (a.2 + b.2) as f32 / 2.0;
+                crossings += 1.0;
+            }
+        }
+    }
+    let position = [sum[0] / crossings, sum[1] / crossings, sum[2] / crossings];
+
+    let distance = |point: Point| {
+        let (x, y, z) = (
+            point.0 as f32 - position[0],
+            point.1 as f32 - position[1],
+            point.2 as f32 - position[2],
+        );
+        x * x + y * y + z * z
+    };
+    let nearest = (0..8)
+        .map(corner)
+        .filter_map(|point| index_at(solid, point).map(|index| (point, index)))
+        .min_by(|a, b| distance(a.0).partial_cmp(&distance(b.0)).unwrap())
+        .unwrap();
+    Some(PosColor { position, color: palette_to_rgba(palette, nearest.1 as usize) })
+}
+
+/// Palette index of the voxel at `point`, which may lie past any side of the grid.
+fn index_at(grid: &DenseGrid, point: Point) -> Option<u8> {
+    if point.0 < 0 || point.1 < 0 || point.2 < 0 {
+        return None;
+    }
+    grid.get([point.0 as u32, point.1 as u32, point.2 as u32])
+}
+
+fn offset(point: Point, axis: usize, by: i32) -> Point {
+    match axis {
+        0 => (point.0 + by, point.1, point.2),
+        1 => (point.0, point.1 + by, point.2),
+        _ => (point.0, point.1, point.2 + by),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dot_vox::{Size, Voxel};
+    use shading::triangle_normal;
+
+    fn sphere(radius: i32) -> Model {
+        let size = radius as u32 * 2 + 1;
+        let mut voxels = Vec::new();
+        for z in -radius..radius + 1 {
+            for y in -radius..radius + 1 {
+                for x in -radius..radius + 1 {
+                    if x * x + y * y + z * z <= radius * radius {
+                        let at = |v: i32| (v + radius) as u8;
+                        voxels.push(Voxel { x: at(x), y: at(y), z: at(z), i: (z + radius) as u8 });
+                    }
+                }
+            }
+        }
+        Model { size: Size { x: size, y: size, z: size }, voxels }
+    }
+
+    fn key(vertex: &PosColor) -> [u32; 3] {
+        let p = vertex.position;
+        [p[0].to_bits(), p[1].to_bits(), p[2].to_bits()]
+    }
+
+    #[test]
+    fn sphere_surface_is_watertight() {
+        let triangles = surface_nets(&sphere(6), &[0xffff_ffff; 256]);
+        assert!(!triangles.is_empty());
+
+        // A closed, consistently wound surface uses every edge once in each direction.
+        let mut edges = HashMap::new();
+        for triangle in triangles.chunks(3) {
+            for &(a, b) in &[(0, 1), (1, 2), (2, 0)] {
+                *edges.entry((key(&triangle[a]), key(&triangle[b]))).or_insert(0) += 1;
+            }
+        }
+        for (&(a, b), &count) in &edges {
+            assert_eq!(count, 1);
+            assert_eq!(edges.get(&(b, a)), Some(&1));
+        }
+    }
+
+    #[test]
+    fn sphere_surface_faces_outwards() {
+        let radius = 6.0;
+        for triangle in surface_nets(&sphere(6), &[0xffff_ffff; 256]).chunks(3) {
+            let normal = match triangle_normal(triangle) {
+                Some(normal) => normal,
+                None => continue,
+            };
+            let outwards: f32 = (0..3)
+                .map(|axis| normal[axis] * (triangle[0].position[axis] - radius))
+                .sum();
+            assert!(outwards > 0.0);
+        }
+    }
+
+    #[test]
+    fn vertices_take_the_colour_of_the_nearest_voxel() {
+        let palette: Vec<u32> = (0..256).map(|i| 0xff00_0000 | i).collect();
+        for vertex in surface_nets(&sphere(4), &palette) {
+            let layer = (vertex.color[0] * 255.0).round();
+            assert!((vertex.position[2] - layer).abs() <= 1.0);
+        }
+    }
+}
@@ -0,0 +1,179 @@
+use amethyst_assets::{Result, SimpleFormat};
+use amethyst_renderer::{Mesh, MeshData, PosNormTex, PosTex, Texture, TextureData,
+                         TextureMetadata};
+use dot_vox::Model;
+use dot_vox_format::{self, DotVoxOptions};
+use lod;
+use meshing::{face_normal, CUBE_OFFSETS};
+
+/// Width of the palette texture produced by `DotVoxPaletteFormat`, one texel per palette entry.
+pub const PALETTE_TEXTURE_WIDTH: usize = 256;
+
+/// Vertex layout produced by `DotVoxTexturedFormat`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TexturedVertices {
+    PosTex,
+    PosNormTex,
+}
+
+/// Options accepted by `DotVoxTexturedFormat`.
+#[derive(Clone, Debug)]
+pub struct TexturedOptions {
+    pub vertices: TexturedVertices,
+    /// Options of the model. `shading` is ignored, as there are no vertex colours to bake it
+    /// into, and so are `extractor`, voxels always being cubes, and `cache_dir`, as the mesh cache
+    /// only holds coloured vertices.
+    pub mesh: DotVoxOptions,
+}
+
+/// Imports the models of a `.vox` file `DotVoxFormat` would, as a textured mesh for amethyst's
+/// standard textured passes. Every voxel samples its colour from the palette texture, which is
+/// loaded from the same file with `DotVoxPaletteFormat`.
+#[derive(Clone)]
+pub struct DotVoxTexturedFormat;
+
+impl SimpleFormat<Mesh> for DotVoxTexturedFormat {
+    const NAME: &'static str = "MAGICAVOXEL_DOT_VOX_TEXTURED";
+    type Options = TexturedOptions;
+
+    fn import(&self, bytes: Vec<u8>, options: TexturedOptions) -> Result<MeshData> {
+        let (models, _) = dot_vox_format::selected_models(&bytes, &options.mesh)?;
+        let (models, origins): (Vec<_>, Vec<_>) = models.into_iter()
+            .map(|(_, model, origin)| (dot_vox_format::prepare(model, &options.mesh), origin))
+            .unzip();
+        dot_vox_format::check_cube_vertices(&models, &options.mesh)?;
+        let mut vertices = Vec::new();
+        for (model, origin) in models.iter().zip(origins) {
+            let mut model_vertices = mesh_model_textured(model);
+            for vertex in &mut model_vertices {
+                if let Some(lod) = options.mesh.lod {
+                    lod::scale_position_to_source(&mut vertex.position, lod.factor);
+                }
+                for axis in 0..3 {
+                    vertex.position[axis] += origin[axis];
+                }
+            }
+            vertices.extend(model_vertices);
+        }
+        Ok(match options.vertices {
+            TexturedVertices::PosNormTex => MeshData::PosNormTex(vertices),
+            TexturedVertices::PosTex => MeshData::PosTex(
+                vertices.into_iter()
+                    .map(|vertex| PosTex {
+                        position: vertex.position,
+                        tex_coord: vertex.tex_coord,
+                    })
+                    .collect()
+            ),
+        })
+    }
+}
+
+/// Imports the palette of a `.vox` file as a `PALETTE_TEXTURE_WIDTH` x 1 texture, the companion
+/// of meshes imported with `DotVoxTexturedFormat`.
+#[derive(Clone)]
+pub struct DotVoxPaletteFormat;
+
+impl SimpleFormat<Texture> for DotVoxPaletteFormat {
+    const NAME: &'static str = "MAGICAVOXEL_DOT_VOX_PALETTE";
+    type Options = ();
+
+    fn import(&self, bytes: Vec<u8>, _: ()) -> Result<TextureData> {
+        let data = dot_vox_format::load(&bytes)?;
+        let metadata = TextureMetadata::srgb_scale()
+            .with_size(PALETTE_TEXTURE_WIDTH as u16, 1);
+        Ok(TextureData::U8(palette_texels(&data.palette), metadata))
+    }
+}
+
+/// Meshes a model like `mesh_model`, but with texture coordinates into the palette texture
+/// instead of colours.
+pub fn mesh_model_textured(model: &Model) -> Vec<PosNormTex> {
+    model.voxels.iter()
+        .flat_map(|voxel| {
+            let tex_coord = palette_tex_coord(voxel.i);
+            CUBE_OFFSETS.chunks(3).flat_map(move |triangle| {
+                let normal = face_normal(triangle);
+                triangle.iter().map(move |&(x, y, z)| PosNormTex {
+                    position: [voxel.x as f32 + x, voxel.y as f32 + y, voxel.z as f32 + z],
+                    normal,
+                    tex_coord,
+                })
+            })
+        })
+        .collect()
+}
+
+/// Texture coordinate of the centre of a palette entry's texel.
+pub fn palette_tex_coord(index: u8) -> [f32; 2] {
+    [(index as f32 + 0.5) / PALETTE_TEXTURE_WIDTH as f32, 0.5]
+}
+
+/// RGBA bytes of the palette texture.
+pub fn palette_texels(palette: &[u32]) -> Vec<u8> {
+    (0..PALETTE_TEXTURE_WIDTH)
+        .flat_map(|index| {
+            let color = palette.get(index).cloned().unwrap_or(0);
+            (0..4).map(move |channel| (color >> (channel * 8)) as u8)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use dot_vox::{Size, Voxel};
+    use scene::tests::SceneBuilder;
+    use scene::LayerFilter;
+
+    #[test]
+    fn every_voxel_samples_the_centre_of_its_palette_texel() {
+        let model = Model {
+            size: Size { x: 2, y: 1, z: 1 },
+            voxels: vec!(Voxel { x: 0, y: 0, z: 0, i: 0 }, Voxel { x: 1, y: 0, z: 0, i: 255 }),
+        };
+        let vertices = mesh_model_textured(&model);
+        assert_eq!(vertices.len(), 72);
+        assert!(vertices[..36].iter().all(|v| v.tex_coord == [0.5 / 256.0, 0.5]));
+        assert!(vertices[36..].iter().all(|v| v.tex_coord == [255.5 / 256.0, 0.5]));
+    }
+
+    #[test]
+    fn normals_point_out_of_the_cube() {
+        let model = Model {
+            size: Size { x: 1, y: 1, z: 1 },
+            voxels: vec!(Voxel { x: 0, y: 0, z: 0, i: 3 }),
+        };
+        for vertex in mesh_model_textured(&model) {
+            let along: f32 = (0..3).map(|axis| vertex.normal[axis] * vertex.position[axis]).sum();
+            assert_eq!(along, 0.5);
+        }
+    }
+
+    #[test]
+    fn layered_imports_place_models_like_coloured_imports() {
+        let scene = SceneBuilder::new()
+            .layer("", false)
+            .model(1, 0, [0; 3], false)
+            .model(2, 0, [4, 0, 0], false)
+            .build();
+        let mesh = DotVoxOptions { layers: Some(LayerFilter::All), ..Default::default() };
+        let vertices = TexturedVertices::PosNormTex;
+        let options = TexturedOptions { vertices, mesh: mesh.clone() };
+        let textured = match DotVoxTexturedFormat.import(scene.clone(), options).unwrap() {
+            MeshData::PosNormTex(vertices) => vertices,
+            result => panic!("Expected textured vertices, received {:?}", result),
+        };
+        let coloured = dot_vox_format::mesh(&scene, &mesh).unwrap();
+        assert_eq!(textured.iter().map(|vertex| vertex.position).collect::<Vec<_>>(),
+                   coloured.iter().map(|vertex| vertex.position).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn palette_texels_are_rgba() {
+        let texels = palette_texels(&[0xff00_00ee, 0x8040_2010]);
+        assert_eq!(texels.len(), PALETTE_TEXTURE_WIDTH * 4);
+        assert_eq!(&texels[..8], &[0xee, 0, 0, 0xff, 0x10, 0x20, 0x40, 0x80]);
+        assert!(texels[8..].iter().all(|&byte| byte == 0));
+    }
+}